@@ -1,12 +1,17 @@
 use std::path::PathBuf;
-use clap::Parser;
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand};
+use mmml_player::{BitConversion, SongPosition, Temperament, WavFormat, FRAME_RATE, TOTAL_VOICES};
 
 // A µMML player / synthesier
 #[derive(Debug, Parser)]
 #[clap(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct MMMLPlayerArgs {
+    #[command(subcommand)]
+    pub command: Option<MMMLPlayerCommand>,
     /// Input file in .mbf, .mmmldata or .mmml
-    pub input_file: PathBuf,
+    #[arg(required = true)]
+    input_file: Option<PathBuf>,
     /// Output file (In wav format)
     #[arg(short, long)]
     output_file: Option<PathBuf>,
@@ -21,11 +26,217 @@ pub struct MMMLPlayerArgs {
     pub ch3_muted: bool,
     /// Mute channel 4
     #[arg(long)]
-    pub ch4_muted: bool
+    pub ch4_muted: bool,
+    /// Render in stereo using the channels panning
+    #[arg(long)]
+    pub stereo: bool,
+    /// Also write one WAV file per channel next to the output file
+    #[arg(long)]
+    pub stems: bool,
+    /// Write headerless little-endian samples to the standard output instead of a WAV file
+    #[arg(long, conflicts_with_all = ["stems", "output_file"])]
+    pub raw: bool,
+    /// Play the song on the audio device instead of writing a WAV file
+    #[cfg(feature = "playback")]
+    #[arg(long, conflicts_with_all = ["stems", "output_file", "raw", "sample_rate"])]
+    pub play: bool,
+    /// Sample rate of the output, resampled from the native rate (141120 Hz, 35280 Hz when summed)
+    #[arg(short, long, value_parser = clap::value_parser!(u32).range(8000..=192000))]
+    pub sample_rate: Option<u32>,
+    /// Sample format of the output: i8 (raw 1-bit levels), i16, i24 or f32
+    #[arg(short, long, default_value = "i8")]
+    pub format: WavFormat,
+    /// Percussion sample bank file played by channel 4 instead of the built-in samples
+    #[arg(long)]
+    pub sample_bank: Option<PathBuf>,
+    /// Recompute the note table for a reference pitch of note a of octave 3 in Hz, about 577.8 Hz in the original table
+    #[arg(long, value_parser = parse_reference)]
+    pub reference: Option<f64>,
+    /// Recompute the note table for a temperament: equal, just or meantone
+    #[arg(long)]
+    pub temperament: Option<Temperament>,
+    /// Recompute the note table for the temperament of a file, the cents above c of the notes c to b
+    #[arg(long, conflicts_with = "temperament")]
+    pub cents: Option<PathBuf>,
+    /// Play at the loop rate of a microcontroller running the original player,
+    /// taking the given number of CPU cycles per main loop iteration
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=1000000))]
+    pub cycles_per_loop: Option<u32>,
+    /// CPU clock in Hz of the microcontroller, with --cycles-per-loop
+    #[arg(long, default_value_t = 8000000, requires = "cycles_per_loop", value_parser = clap::value_parser!(u32).range(1000..=1000000000))]
+    pub cpu_clock: u32,
+    /// Keep a single macro return address like the original player (nested macros don't return)
+    #[arg(long)]
+    pub single_macro_level: bool,
+    /// Sum the voices into one multi-level sample instead of interleaving them (preview quality)
+    #[arg(long)]
+    pub summed: bool,
+    /// Comma separated gains of the channels 1 to 4 in the summed mix, out of 127
+    #[arg(long, value_delimiter = ',', requires = "summed", value_parser = clap::value_parser!(u8).range(0..=127))]
+    pub gains: Vec<u8>,
+    /// Stop once every channel played through the given number of times
+    #[arg(long, conflicts_with_all = ["first_end", "duration", "end"], value_parser = clap::value_parser!(u32).range(1..))]
+    pub loops: Option<u32>,
+    /// Stop as soon as any channel reaches its end
+    #[arg(long, conflicts_with_all = ["duration", "end"])]
+    pub first_end: bool,
+    /// Stop after the given number of seconds
+    #[arg(long, conflicts_with = "end", value_parser = parse_duration)]
+    pub duration: Option<f64>,
+    /// Start playing from a position: seconds (12.5 or 12.5s), ticks (300t) or bars from 1 (40b)
+    #[arg(long, value_parser = parse_position)]
+    pub start: Option<PositionArg>,
+    /// Stop at a position: seconds (12.5 or 12.5s), ticks (300t) or bars from 1 (40b)
+    #[arg(long, value_parser = parse_position)]
+    pub end: Option<PositionArg>,
+    /// Fade out over the given number of seconds once the song stops
+    #[arg(long, default_value_t = 0.0, value_parser = parse_fade)]
+    pub fade: f64
+}
+
+#[derive(Debug, Subcommand)]
+pub enum MMMLPlayerCommand {
+    /// Print the µMML bytecode as an annotated listing
+    Disasm {
+        /// Input file in .mbf, .mmmldata or .mmml
+        input_file: PathBuf
+    },
+    /// Print the song length and structure without rendering it
+    Info {
+        /// Input file in .mbf, .mmmldata or .mmml
+        input_file: PathBuf,
+        /// Percussion sample bank file played by channel 4 instead of the built-in samples
+        #[arg(long)]
+        sample_bank: Option<PathBuf>
+    },
+    /// Turn µMML bytecode back into µMML source code
+    Decompile {
+        /// Input file in .mbf or .mmmldata
+        input_file: PathBuf,
+        /// Output file (In µMML format)
+        #[arg(short, long)]
+        output_file: Option<PathBuf>
+    },
+    /// Export the song as a Standard MIDI File
+    Midi {
+        /// Input file in .mbf, .mmmldata or .mmml
+        input_file: PathBuf,
+        /// Output file (In MIDI format)
+        #[arg(short, long)]
+        output_file: Option<PathBuf>,
+        /// Percussion sample bank file played by channel 4 instead of the built-in samples
+        #[arg(long)]
+        sample_bank: Option<PathBuf>
+    },
+    /// Convert a Standard MIDI File into µMML bytecode
+    Import {
+        /// Input file in .mid
+        input_file: PathBuf,
+        /// Output file (In .mbf format)
+        #[arg(short, long)]
+        output_file: Option<PathBuf>
+    },
+    /// Convert a short WAV file into a 1-bit percussion sample added to a sample bank
+    Sample {
+        /// Input file in .wav
+        input_file: PathBuf,
+        /// Output file (In sample bank format)
+        #[arg(short, long)]
+        output_file: Option<PathBuf>,
+        /// Sample bank the sample is added to, the built-in samples by default
+        #[arg(long)]
+        sample_bank: Option<PathBuf>,
+        /// 1-bit conversion: threshold, delta-sigma or pwm
+        #[arg(short, long, default_value = "delta-sigma")]
+        conversion: BitConversion,
+        /// Also write the sample as the sampler plays it into a WAV file
+        #[arg(short, long)]
+        preview: Option<PathBuf>
+    }
+}
+
+const BAR_TICKS: u64 = 128; // a whole note
+const MAX_SECONDS: f64 = u32::MAX as f64 / FRAME_RATE as f64; // longest time counted in 32-bit frames (about 33 hours)
+
+/// Song position of the command line, seconds are converted once the frame rate is known
+#[derive(Debug, Clone, Copy)]
+pub enum PositionArg {
+    Seconds(f64),
+    Position(SongPosition)
+}
+
+impl PositionArg {
+    pub fn song_position(self, frame_rate: f64) -> SongPosition {
+        match self {
+            PositionArg::Seconds(seconds) => SongPosition::Frames((seconds * frame_rate).round() as u64),
+            PositionArg::Position(position) => position
+        }
+    }
+}
+
+/// Parse a song position in seconds, ticks or bars
+fn parse_position(position: &str) -> Result<PositionArg, String> {
+    let invalid: String = format!("invalid position '{}', expected seconds (12.5s), ticks (300t) or bars (40b)", position);
+    if let Some(ticks) = position.strip_suffix('t') {
+        return Ok(PositionArg::Position(SongPosition::Ticks(ticks.parse().map_err(|_| invalid.clone())?)));
+    }
+    if let Some(bar) = position.strip_suffix('b') {
+        let bar: u64 = bar.parse().map_err(|_| invalid.clone())?;
+        return Ok(PositionArg::Position(SongPosition::Ticks(bar.saturating_sub(1) * BAR_TICKS)));
+    }
+    let seconds: f64 = position.strip_suffix('s').unwrap_or(position).parse().map_err(|_| invalid.clone())?;
+    if seconds < 0.0 {
+        return Err(invalid);
+    }
+    Ok(PositionArg::Seconds(seconds))
+}
+
+/// Parse a number of seconds, up to `MAX_SECONDS`
+fn parse_seconds(seconds: &str, allows_zero: bool) -> Result<f64, String> {
+    match seconds.parse::<f64>() {
+        Ok(value) if value > 0.0 && value <= MAX_SECONDS => Ok(value),
+        Ok(value) if allows_zero && value == 0.0 => Ok(value),
+        _ => {
+            let minimum: &str = if allows_zero { "0" } else { "more than 0" };
+            Err(format!("invalid duration '{}', expected {} to {:.0} seconds", seconds, minimum, MAX_SECONDS))
+        }
+    }
+}
+
+/// Parse the duration of the song in seconds
+fn parse_duration(duration: &str) -> Result<f64, String> {
+    parse_seconds(duration, false)
+}
+
+/// Parse the duration of the fade out in seconds, 0 for none
+fn parse_fade(fade: &str) -> Result<f64, String> {
+    parse_seconds(fade, true)
+}
+
+/// Parse a reference pitch in Hz
+fn parse_reference(reference: &str) -> Result<f64, String> {
+    match reference.parse::<f64>() {
+        Ok(reference) if (100.0..=1000.0).contains(&reference) => Ok(reference),
+        _ => Err(format!("invalid reference pitch '{}', expected 100 to 1000 Hz", reference))
+    }
 }
 
 impl MMMLPlayerArgs {
+    /// Parse the command line, including the checks clap can't express
+    pub fn try_parse_checked() -> Result<Self, clap::Error> {
+        let args: MMMLPlayerArgs = Self::try_parse()?;
+        if args.gains.len() > TOTAL_VOICES {
+            return Err(Self::command().error(ErrorKind::TooManyValues, format!("--gains takes at most {} values", TOTAL_VOICES)));
+        }
+        Ok(args)
+    }
+
+    pub fn get_input_path(&self) -> PathBuf {
+        self.input_file.clone().unwrap_or_default()
+    }
+
     pub fn get_output_path(&self) -> PathBuf {
-        self.output_file.clone().unwrap_or(self.input_file.with_extension("wav"))
+        self.output_file.clone().unwrap_or(self.get_input_path().with_extension("wav"))
     }
 }
+
@@ -0,0 +1,137 @@
+/// µMML bytecode decoding
+///
+/// Each instruction is one byte split in two nibbles, function commands
+/// (first nibble 0xF) may take an additional argument byte.
+/// The data starts with a header of big endian pointers: one per channel
+/// followed by one per macro.
+use crate::mmml_engine::TOTAL_VOICES;
+
+pub const LOOP_START: u8 = 0x00;
+pub const LOOP_END: u8 = 0x01;
+pub const MACRO: u8 = 0x02;
+pub const TEMPO: u8 = 0x03;
+pub const TRANSPOSE: u8 = 0x04;
+pub const INSTRUMENT: u8 = 0x05;
+pub const TIE: u8 = 0x06;
+pub const PANNING: u8 = 0x07;
+pub const DEBUG_FLAG: u8 = 0x0E;
+pub const CHANNEL_END: u8 = 0x0F;
+pub const OCTAVE: u8 = 0x0D;
+pub const VOLUME: u8 = 0x0E;
+pub const FUNCTION: u8 = 0x0F;
+
+/// A decoded bytecode instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// Note (1 to 12, 0 is a rest) with its duration nibble
+    Note { note: u8, duration: u8 },
+    Octave(u8),
+    Volume(u8),
+    LoopStart(u8),
+    LoopEnd,
+    Macro(u8),
+    Tempo(u8),
+    Transpose(i8),
+    Instrument(u8),
+    Tie,
+    Panning(i8),
+    DebugFlag,
+    ChannelEnd,
+    /// Unsupported function command byte
+    Unknown(u8),
+}
+
+impl Instruction {
+    /// Decode the instruction at the offset, `None` if the data ends before it does.
+    pub fn decode(mmml_source: &[u8], offset: usize) -> Option<Instruction> {
+        let buffer0 = *mmml_source.get(offset)?;
+        // First nibble of data
+        let buffer1 = (buffer0 >> 4) & 0x0F;
+        // Second nibble of data
+        let buffer2 = buffer0 & 0x0F;
+
+        let instruction = match buffer1 {
+            OCTAVE => Instruction::Octave(buffer2),
+            VOLUME => Instruction::Volume(buffer2),
+            FUNCTION => match buffer2 {
+                LOOP_END => Instruction::LoopEnd,
+                TIE => Instruction::Tie,
+                DEBUG_FLAG => Instruction::DebugFlag,
+                CHANNEL_END => Instruction::ChannelEnd,
+                LOOP_START | MACRO | TEMPO | TRANSPOSE | INSTRUMENT | PANNING => {
+                    // Commands that require an additional byte.
+                    let buffer3 = *mmml_source.get(offset + 1)?;
+                    match buffer2 {
+                        LOOP_START => Instruction::LoopStart(buffer3),
+                        MACRO => Instruction::Macro(buffer3),
+                        TEMPO => Instruction::Tempo(buffer3),
+                        TRANSPOSE => Instruction::Transpose(buffer3 as i8),
+                        INSTRUMENT => Instruction::Instrument(buffer3),
+                        _ => Instruction::Panning(buffer3 as i8),
+                    }
+                },
+                _ => Instruction::Unknown(buffer0),
+            },
+            note => Instruction::Note { note, duration: buffer2 },
+        };
+        Some(instruction)
+    }
+
+    /// Append the bytes of the instruction
+    pub fn encode(&self, data: &mut Vec<u8>) {
+        let function = |command: u8| (FUNCTION << 4) | command;
+        match *self {
+            Instruction::Note { note, duration } => data.push((note << 4) | (duration & 0x0F)),
+            Instruction::Octave(octave) => data.push((OCTAVE << 4) | (octave & 0x0F)),
+            Instruction::Volume(volume) => data.push((VOLUME << 4) | (volume & 0x0F)),
+            Instruction::LoopStart(count) => data.extend_from_slice(&[function(LOOP_START), count]),
+            Instruction::LoopEnd => data.push(function(LOOP_END)),
+            Instruction::Macro(index) => data.extend_from_slice(&[function(MACRO), index]),
+            Instruction::Tempo(tempo) => data.extend_from_slice(&[function(TEMPO), tempo]),
+            Instruction::Transpose(transpose) => data.extend_from_slice(&[function(TRANSPOSE), transpose as u8]),
+            Instruction::Instrument(instrument) => data.extend_from_slice(&[function(INSTRUMENT), instrument]),
+            Instruction::Tie => data.push(function(TIE)),
+            Instruction::Panning(pan) => data.extend_from_slice(&[function(PANNING), pan as u8]),
+            Instruction::DebugFlag => data.push(function(DEBUG_FLAG)),
+            Instruction::ChannelEnd => data.push(function(CHANNEL_END)),
+            Instruction::Unknown(command) => data.push(command),
+        }
+    }
+
+    /// Size of the instruction in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::LoopStart(_) | Instruction::Macro(_) | Instruction::Tempo(_) |
+            Instruction::Transpose(_) | Instruction::Instrument(_) | Instruction::Panning(_) => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// Length in ticks (minus one) of a note duration nibble
+pub fn note_length(duration: u8) -> u8 {
+    match duration {
+        0..=7 => 0x7F >> duration,        // Standard duration
+        _ => 95 >> (duration & 7),        // Dotted (1 + 1/2) duration
+    }
+}
+
+/// Read a big endian pointer of the data
+pub fn read_pointer(mmml_source: &[u8], offset: usize) -> Option<u16> {
+    Some(((*mmml_source.get(offset)? as u16) << 8) | (*mmml_source.get(offset + 1)? as u16))
+}
+
+/// Header offset of a channel start pointer
+pub fn channel_pointer_offset(channel: usize) -> usize {
+    channel * 2
+}
+
+/// Header offset of a macro pointer
+pub fn macro_pointer_offset(index: u8) -> usize {
+    ((index as usize) + TOTAL_VOICES) * 2
+}
+
+/// Number of macros of the header, from its size
+pub fn macro_count(header_size: u16) -> usize {
+    (header_size as usize / 2).saturating_sub(TOTAL_VOICES)
+}
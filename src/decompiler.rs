@@ -0,0 +1,81 @@
+/// µMML bytecode decompiler
+///
+/// Turns the channel streams and the macro table back into µMML source:
+/// one line per channel (`A` to `D`) followed by one line per macro (`#n`),
+/// with loops rebuilt as `[ ... ]n` and macros called with `mn`.
+use crate::{
+    bytecode::{channel_pointer_offset, macro_count, macro_pointer_offset, read_pointer, Instruction},
+    error::MMMLError,
+    mmml_engine::TOTAL_VOICES
+};
+
+const NOTE_NAMES: [&str; 13] = ["r", "c", "c+", "d", "d+", "e", "f", "f+", "g", "g+", "a", "a+", "b"];
+const CHANNEL_NAMES: [char; TOTAL_VOICES] = ['A', 'B', 'C', 'D'];
+
+/// Decompile µMML bytecode into µMML source code
+pub fn decompile(mmml_source: &[u8]) -> Result<String, MMMLError> {
+    let header_size: u16 = read_header_pointer(mmml_source, channel_pointer_offset(0))?;
+    let mut source: String = String::new();
+    for (channel, name) in CHANNEL_NAMES.iter().enumerate() {
+        let pointer: u16 = read_header_pointer(mmml_source, channel_pointer_offset(channel))?;
+        source.push(*name);
+        decompile_stream(&mut source, mmml_source, pointer as usize, Some(channel))?;
+        source.push('\n');
+    }
+    for index in 0..macro_count(header_size) {
+        let pointer: u16 = read_header_pointer(mmml_source, macro_pointer_offset(index as u8))?;
+        source.push_str(&format!("#{}", index));
+        decompile_stream(&mut source, mmml_source, pointer as usize, None)?;
+        source.push('\n');
+    }
+    Ok(source)
+}
+
+fn read_header_pointer(mmml_source: &[u8], offset: usize) -> Result<u16, MMMLError> {
+    read_pointer(mmml_source, offset)
+        .ok_or(MMMLError::MalformedBytecode { offset, channel: None, reason: "truncated header" })
+}
+
+/// Append the µMML code of a stream up to its channel end
+fn decompile_stream(source: &mut String, mmml_source: &[u8], start: usize, channel: Option<usize>) -> Result<(), MMMLError> {
+    let malformed = |offset: usize, reason: &'static str| MMMLError::MalformedBytecode { offset, channel, reason };
+    let mut offset: usize = start;
+    let mut loop_counts: Vec<u8> = Vec::new();
+    loop {
+        let instruction: Instruction = Instruction::decode(mmml_source, offset)
+            .ok_or(malformed(offset, "unexpected end of data"))?;
+        let code: String = match instruction {
+            Instruction::Note { note, duration } => {
+                let dotted: &str = if duration > 7 { "." } else { "" };
+                format!("{}{}{}", NOTE_NAMES[note as usize], 1 << (duration & 7), dotted)
+            },
+            Instruction::Octave(octave) => format!("o{}", octave),
+            Instruction::Volume(volume) => format!("v{}", volume),
+            Instruction::LoopStart(count) => {
+                loop_counts.push(count);
+                "[".to_string()
+            },
+            Instruction::LoopEnd => {
+                let count: u8 = loop_counts.pop().ok_or(malformed(offset, "loop end without loop start"))?;
+                format!("]{}", count)
+            },
+            Instruction::Macro(index) => format!("m{}", index),
+            Instruction::Tempo(tempo) => format!("t{}", tempo),
+            Instruction::Transpose(transpose) => format!("k{}", transpose),
+            Instruction::Instrument(instrument) => format!("@{}", instrument),
+            Instruction::Tie => "&".to_string(),
+            Instruction::Panning(pan) => format!("p{}", pan),
+            Instruction::DebugFlag => "!".to_string(),
+            Instruction::ChannelEnd => {
+                if !loop_counts.is_empty() {
+                    return Err(malformed(offset, "loop start without loop end"));
+                }
+                return Ok(());
+            },
+            Instruction::Unknown(_) => return Err(malformed(offset, "unknown command")),
+        };
+        source.push(' ');
+        source.push_str(&code);
+        offset += instruction.size();
+    }
+}
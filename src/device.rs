@@ -0,0 +1,164 @@
+/// Live audio playback
+///
+/// Audio sink playing the rendered samples on the default output device,
+/// available with the `playback` feature.
+use std::{collections::VecDeque, fmt::Display, sync::{Arc, Mutex}, thread, time::{Duration, Instant}};
+
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    Device, FromSample, OutputCallbackInfo, SampleFormat, SizedSample, Stream, StreamConfig, SupportedStreamConfig
+};
+
+use crate::{
+    error::MMMLError,
+    sink::{AudioSink, EncodedSample, SampleEncoder, StreamSpec},
+    wav::WavFormat
+};
+
+const QUEUE_DURATION: u32 = 5;                   // audio queued ahead of the device (1/5 s)
+const WAIT_TIME: Duration = Duration::from_millis(10);
+
+/// Playback state shared with the audio callback
+#[derive(Default)]
+struct Playback {
+    /// Samples waiting to be played
+    queue: VecDeque<f32>,
+    /// Time the device plays the last queued sample at, set by the callback once it emptied the queue
+    end: Option<Instant>
+}
+
+type SharedPlayback = Arc<Mutex<Playback>>;
+
+/// Sink playing the samples on the default output device
+pub struct DeviceSink {
+    device: Device,
+    config: SupportedStreamConfig,
+    stream: Option<Stream>,
+    playback: SharedPlayback,
+    encoder: Option<SampleEncoder>,
+    /// Channels of the rendered stream
+    channels: usize,
+    /// Rendered frame being gathered
+    frame: Vec<f32>
+}
+
+fn device_error<E: Display>(err: E) -> MMMLError {
+    MMMLError::Device(err.to_string())
+}
+
+fn build_stream<T: SizedSample + FromSample<f32>>(device: &Device, config: &StreamConfig, playback: SharedPlayback) -> Result<Stream, MMMLError> {
+    let samples_per_second: f64 = config.sample_rate.0 as f64 * config.channels as f64;
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], info: &OutputCallbackInfo| {
+            let mut playback = playback.lock().unwrap();
+            let mut consumed: usize = 0;
+            for slot in data.iter_mut() {
+                let sample: Option<f32> = playback.queue.pop_front();
+                consumed += sample.is_some() as usize;
+                *slot = T::from_sample(sample.unwrap_or(0.0));
+            }
+            if playback.queue.is_empty() && playback.end.is_none() {
+                // The buffer starts playing after the device latency, its queued samples first
+                let timestamp = info.timestamp();
+                let latency: Duration = timestamp.playback.duration_since(&timestamp.callback).unwrap_or_default();
+                playback.end = Some(Instant::now() + latency + Duration::from_secs_f64(consumed as f64 / samples_per_second));
+            }
+        },
+        |err| eprintln!("Audio device error: {}", err),
+        None
+    ).map_err(device_error)
+}
+
+impl DeviceSink {
+    /// Sink playing on the default output device of the system
+    pub fn new() -> Result<Self, MMMLError> {
+        let device: Device = cpal::default_host()
+            .default_output_device()
+            .ok_or(MMMLError::Device("no output device available".to_string()))?;
+        let config: SupportedStreamConfig = device.default_output_config().map_err(device_error)?;
+        Ok(DeviceSink {
+            device,
+            config,
+            stream: None,
+            playback: Arc::new(Mutex::new(Playback::default())),
+            encoder: None,
+            channels: 1,
+            frame: Vec::new()
+        })
+    }
+
+    /// Sample rate of the device, the song must be rendered at this rate
+    pub fn sample_rate(&self) -> u32 {
+        self.config.sample_rate().0
+    }
+
+    fn device_channels(&self) -> usize {
+        self.config.channels() as usize
+    }
+}
+
+impl AudioSink for DeviceSink {
+    fn open(&mut self, spec: StreamSpec) -> Result<(), MMMLError> {
+        if spec.sample_rate != self.sample_rate() {
+            return Err(MMMLError::Device(format!("the device plays at {} Hz, not {} Hz", self.sample_rate(), spec.sample_rate)));
+        }
+        self.channels = spec.channels as usize;
+        self.encoder = Some(SampleEncoder::new(WavFormat::F32, spec));
+        let config: StreamConfig = self.config.config();
+        let stream: Stream = match self.config.sample_format() {
+            SampleFormat::F32 => build_stream::<f32>(&self.device, &config, self.playback.clone())?,
+            SampleFormat::I16 => build_stream::<i16>(&self.device, &config, self.playback.clone())?,
+            SampleFormat::U16 => build_stream::<u16>(&self.device, &config, self.playback.clone())?,
+            SampleFormat::I32 => build_stream::<i32>(&self.device, &config, self.playback.clone())?,
+            format => return Err(MMMLError::Device(format!("unsupported sample format {:?}", format)))
+        };
+        stream.play().map_err(device_error)?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    fn write(&mut self, levels: &[f32]) -> Result<(), MMMLError> {
+        let device_channels: usize = self.device_channels();
+        let capacity: usize = self.sample_rate() as usize * device_channels / QUEUE_DURATION as usize;
+        let encoder: &mut SampleEncoder = self.encoder.as_mut().expect("device sink written before being opened");
+        for level in levels {
+            if let EncodedSample::F32(sample) = encoder.encode(*level) {
+                self.frame.push(sample);
+            }
+            if self.frame.len() < self.channels {
+                continue;
+            }
+            // Wait for the device to catch up
+            while self.playback.lock().unwrap().queue.len() >= capacity {
+                thread::sleep(WAIT_TIME);
+            }
+            // Mono goes to every speaker, stereo to the first two or downmixed on a mono device
+            let mut playback = self.playback.lock().unwrap();
+            for channel in 0..device_channels {
+                let sample: f32 = match (self.channels, device_channels) {
+                    (1, _) => self.frame[0],
+                    (_, 1) => self.frame.iter().sum::<f32>() / self.channels as f32,
+                    _ => self.frame.get(channel).copied().unwrap_or(0.0)
+                };
+                playback.queue.push_back(sample);
+            }
+            playback.end = None;
+            self.frame.clear();
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), MMMLError> {
+        // Wait for the callback to take the last samples, then for the device to play them
+        let end: Instant = loop {
+            if let Some(end) = self.playback.lock().unwrap().end {
+                break end;
+            }
+            thread::sleep(WAIT_TIME);
+        };
+        thread::sleep(end.saturating_duration_since(Instant::now()));
+        self.stream = None;
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+/// µMML bytecode disassembler
+///
+/// Prints the header pointers and the instructions of every channel and
+/// macro stream as an annotated listing with their byte offsets.
+use std::fmt::Write;
+
+use crate::{
+    bytecode::{channel_pointer_offset, macro_count, macro_pointer_offset, note_length, read_pointer, Instruction},
+    mmml_engine::TOTAL_VOICES
+};
+
+const NOTE_NAMES: [&str; 13] = ["", "c", "c+", "d", "d+", "e", "f", "f+", "g", "g+", "a", "a+", "b"];
+const SAMPLE_NAMES: [&str; 6] = ["", "bwoop", "beep", "kick", "snare", "hi-hat"];
+
+/// Disassemble µMML bytecode into a readable listing
+pub fn disassemble(mmml_source: &[u8]) -> String {
+    let mut listing: String = String::new();
+    let header_size: u16 = match read_pointer(mmml_source, channel_pointer_offset(0)) {
+        Some(header_size) => header_size,
+        None => {
+            listing.push_str("; Truncated header\n");
+            return listing;
+        }
+    };
+
+    let _ = writeln!(listing, "; Header ({} bytes)", header_size);
+    let mut streams: Vec<(String, usize, bool)> = Vec::new();
+    for channel in 0..TOTAL_VOICES {
+        let offset: usize = channel_pointer_offset(channel);
+        if let Some(pointer) = write_pointer(&mut listing, mmml_source, offset, &format!("channel {}", channel + 1)) {
+            streams.push((format!("Channel {}", channel + 1), pointer, channel == TOTAL_VOICES - 1));
+        }
+    }
+    for index in 0..macro_count(header_size) {
+        let offset: usize = macro_pointer_offset(index as u8);
+        if let Some(pointer) = write_pointer(&mut listing, mmml_source, offset, &format!("macro {}", index)) {
+            streams.push((format!("Macro {}", index), pointer, false));
+        }
+    }
+
+    for (name, pointer, is_sampler) in streams {
+        let _ = writeln!(listing, "\n; {}", name);
+        write_stream(&mut listing, mmml_source, pointer, is_sampler);
+    }
+    listing
+}
+
+/// Write a header pointer line, returning the pointer
+fn write_pointer(listing: &mut String, mmml_source: &[u8], offset: usize, name: &str) -> Option<usize> {
+    match read_pointer(mmml_source, offset) {
+        Some(pointer) => {
+            let _ = writeln!(listing, "{:04X}: {:02X} {:02X}     {} -> {:04X}", offset, pointer >> 8, pointer & 0xFF, name, pointer);
+            Some(pointer as usize)
+        },
+        None => {
+            let _ = writeln!(listing, "{:04X}: ; {} pointer truncated", offset, name);
+            None
+        }
+    }
+}
+
+/// Write the instructions of a stream up to its channel end
+fn write_stream(listing: &mut String, mmml_source: &[u8], start: usize, is_sampler: bool) {
+    let mut offset: usize = start;
+    let mut depth: usize = 0;
+    loop {
+        let instruction: Instruction = match Instruction::decode(mmml_source, offset) {
+            Some(instruction) => instruction,
+            None => {
+                let _ = writeln!(listing, "{:04X}: ; unexpected end of data", offset);
+                return;
+            }
+        };
+        let bytes: String = mmml_source[offset..offset + instruction.size()].iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<String>>()
+            .join(" ");
+        if instruction == Instruction::LoopEnd {
+            depth = depth.saturating_sub(1);
+        }
+        let _ = writeln!(listing, "{:04X}: {:<8}  {}{}", offset, bytes, "  ".repeat(depth), describe(&instruction, is_sampler));
+        match instruction {
+            Instruction::LoopStart(_) => depth += 1,
+            Instruction::ChannelEnd => return,
+            _ => {}
+        }
+        offset += instruction.size();
+    }
+}
+
+/// Human readable form of an instruction
+fn describe(instruction: &Instruction, is_sampler: bool) -> String {
+    match *instruction {
+        Instruction::Note { note, duration } => {
+            let name: &str = if is_sampler {
+                SAMPLE_NAMES.get(note as usize).copied().unwrap_or("?")
+            } else {
+                NOTE_NAMES[note as usize]
+            };
+            let dotted: &str = if duration > 7 { "." } else { "" };
+            let value: String = format!("1/{}{} ({} ticks)", 1 << (duration & 7), dotted, note_length(duration) as u16 + 1);
+            if note == 0 {
+                format!("rest {}", value)
+            } else {
+                format!("note {} {}", name, value)
+            }
+        },
+        Instruction::Octave(octave) => format!("octave {}", octave),
+        Instruction::Volume(volume) => format!("volume {}", volume),
+        Instruction::LoopStart(count) => format!("loop start x{}", count),
+        Instruction::LoopEnd => "loop end".to_string(),
+        Instruction::Macro(index) => format!("macro {}", index),
+        Instruction::Tempo(tempo) => format!("tempo {}", tempo),
+        Instruction::Transpose(transpose) => format!("transpose {:+}", transpose),
+        Instruction::Instrument(instrument) => format!("instrument {}", instrument),
+        Instruction::Tie => "tie".to_string(),
+        Instruction::Panning(pan) => format!("panning {:+}", pan),
+        Instruction::DebugFlag => "debug flag".to_string(),
+        Instruction::ChannelEnd => "channel end".to_string(),
+        Instruction::Unknown(command) => format!("unknown command {:02X}", command),
+    }
+}
@@ -0,0 +1,97 @@
+use std::{fmt::{Display, Formatter, Result as FmtResult}, io::Error};
+
+/// Errors of the µMML player
+#[derive(Debug)]
+pub enum MMMLError {
+    /// Failed to read or write a file
+    Io(Error),
+    /// The µMML source could not be compiled
+    Compile(Error),
+    /// The µMML bytecode is invalid at the given offset
+    MalformedBytecode {
+        offset: usize,
+        channel: Option<usize>,
+        reason: &'static str
+    },
+    /// Failed to write the audio output
+    Output(hound::Error),
+    /// The MIDI file could not be read
+    InvalidMidi(&'static str),
+    /// The audio device could not play the song
+    Device(String),
+    /// The sample bank file could not be read
+    InvalidSampleBank(&'static str),
+    /// The tuning file could not be read
+    InvalidTuning(&'static str),
+    /// The start or end position can't be played
+    InvalidPosition(&'static str),
+    /// The decompiled source doesn't compile back into the original bytecode
+    DecompileMismatch
+}
+
+impl MMMLError {
+    pub(crate) fn malformed(offset: usize, channel: usize, reason: &'static str) -> Self {
+        MMMLError::MalformedBytecode { offset, channel: Some(channel), reason }
+    }
+
+    /// Process exit code for this class of error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            MMMLError::Io(_) => 2,
+            MMMLError::Compile(_) => 3,
+            MMMLError::MalformedBytecode { .. } => 4,
+            MMMLError::Output(_) => 5,
+            MMMLError::InvalidMidi(_) => 6,
+            MMMLError::Device(_) => 7,
+            MMMLError::InvalidSampleBank(_) => 8,
+            MMMLError::InvalidTuning(_) => 9,
+            MMMLError::InvalidPosition(_) => 10,
+            MMMLError::DecompileMismatch => 11
+        }
+    }
+}
+
+impl Display for MMMLError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            MMMLError::Io(err) => write!(f, "IO error: {}", err),
+            MMMLError::Compile(err) => write!(f, "Compilation error: {}", err),
+            MMMLError::MalformedBytecode { offset, channel: Some(channel), reason } => {
+                write!(f, "Malformed bytecode at 0x{:04X} (channel {}): {}", offset, channel + 1, reason)
+            },
+            MMMLError::MalformedBytecode { offset, channel: None, reason } => {
+                write!(f, "Malformed bytecode at 0x{:04X}: {}", offset, reason)
+            },
+            MMMLError::Output(err) => write!(f, "Output error: {}", err),
+            MMMLError::InvalidMidi(reason) => write!(f, "Invalid MIDI file: {}", reason),
+            MMMLError::Device(reason) => write!(f, "Audio device error: {}", reason),
+            MMMLError::InvalidSampleBank(reason) => write!(f, "Invalid sample bank: {}", reason),
+            MMMLError::InvalidTuning(reason) => write!(f, "Invalid tuning: {}", reason),
+            MMMLError::InvalidPosition(reason) => write!(f, "Invalid position: {}", reason),
+            MMMLError::DecompileMismatch => write!(f, "Decompilation error: the recompiled bytecode differs from the original")
+        }
+    }
+}
+
+impl std::error::Error for MMMLError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MMMLError::Io(err) | MMMLError::Compile(err) => Some(err),
+            MMMLError::Output(err) => Some(err),
+            MMMLError::MalformedBytecode { .. } | MMMLError::InvalidMidi(_) | MMMLError::Device(_) | MMMLError::InvalidSampleBank(_)
+            | MMMLError::InvalidTuning(_) | MMMLError::InvalidPosition(_) | MMMLError::DecompileMismatch => None
+        }
+    }
+}
+
+impl From<Error> for MMMLError {
+    fn from(err: Error) -> Self {
+        MMMLError::Io(err)
+    }
+}
+
+impl From<hound::Error> for MMMLError {
+    fn from(err: hound::Error) -> Self {
+        MMMLError::Output(err)
+    }
+}
@@ -0,0 +1,167 @@
+/// Song structure analysis
+///
+/// Drives the sequencer of `MMMLSynthesizer` without synthesis to measure
+/// the song and its channels, which is nearly instant even for long songs.
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use crate::{
+    bytecode::{macro_count, read_pointer},
+    error::MMMLError,
+    mmml_engine::{MMMLSynthesizer, SequencerEvent, MAX_TICKS, TOTAL_VOICES}
+};
+
+/// Position in the song
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SongTime {
+    pub ticks: u64,
+    pub frames: u64
+}
+
+impl SongTime {
+    /// Display the time for a synthesizer running at `frame_rate`, see `MMMLSynthesizer::frame_rate`
+    pub fn display(self, frame_rate: f64) -> impl Display {
+        SongTimeDisplay { time: self, frame_rate }
+    }
+}
+
+struct SongTimeDisplay {
+    time: SongTime,
+    frame_rate: f64
+}
+
+impl Display for SongTimeDisplay {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let milliseconds: u64 = (self.time.frames as f64 * 1000.0 / self.frame_rate) as u64;
+        write!(f, "{}:{:02}.{:03} ({} ticks)", milliseconds / 60000, milliseconds / 1000 % 60, milliseconds % 1000, self.time.ticks)
+    }
+}
+
+/// Statistics of a channel over its first pass
+///
+/// A channel restarts from its first byte once it reaches its end, so it has
+/// no intro: the whole channel is the loop.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChannelInfo {
+    /// Length of the channel data before it restarts from its start, `None` if it never ends
+    pub loop_length: Option<SongTime>,
+    pub notes: u32,
+    pub rests: u32,
+    /// Deepest loop nesting reached
+    pub max_loop_depth: u8
+}
+
+/// Tempo command met while playing the song
+#[derive(Debug, Clone, Copy)]
+pub struct TempoChange {
+    pub time: SongTime,
+    pub channel: usize,
+    pub tempo: u8
+}
+
+/// Structure of a song, see `analyze`
+#[derive(Debug, Clone)]
+pub struct SongInfo {
+    /// Length until every channel ends on the same tick, `None` if it doesn't happen
+    pub length: Option<SongTime>,
+    pub channels: [ChannelInfo; TOTAL_VOICES],
+    /// Tempo commands of the first pass of every channel
+    pub tempo_changes: Vec<TempoChange>,
+    /// Number of calls of each macro of the header table over the first pass of every channel
+    pub macro_calls: Vec<u32>,
+    /// Frames per second of the synthesizer the song was analyzed with
+    pub frame_rate: f64
+}
+
+impl Display for SongInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self.length {
+            Some(length) => writeln!(f, "Song length: {}", length.display(self.frame_rate))?,
+            None => writeln!(f, "Song length: never ends, the channels don't reach their end on the same tick within {} ticks", MAX_TICKS)?
+        }
+        for (channel, info) in self.channels.iter().enumerate() {
+            write!(f, "Channel {}: ", channel + 1)?;
+            match info.loop_length {
+                Some(length) => write!(f, "loops from its start (no intro) after {}", length.display(self.frame_rate))?,
+                None => write!(f, "never ends")?
+            }
+            writeln!(f, ", {} notes, {} rests, loop nesting {}", info.notes, info.rests, info.max_loop_depth)?;
+        }
+        let max_loop_depth: u8 = self.channels.iter().map(|info| info.max_loop_depth).max().unwrap_or(0);
+        writeln!(f, "Max loop nesting: {}", max_loop_depth)?;
+        writeln!(f, "Tempo changes: {}", self.tempo_changes.len())?;
+        for change in &self.tempo_changes {
+            writeln!(f, "\t{} channel {}: t{}", change.time.display(self.frame_rate), change.channel + 1, change.tempo)?;
+        }
+        writeln!(f, "Macros: {}", self.macro_calls.len())?;
+        for (index, calls) in self.macro_calls.iter().enumerate() {
+            match calls {
+                0 => writeln!(f, "\t#{}: unused", index)?,
+                _ => writeln!(f, "\t#{}: {} calls", index, calls)?
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Analyze the structure of µMML bytecode by running its sequencer.
+pub fn analyze(mmml_data: &[u8]) -> Result<SongInfo, MMMLError> {
+    analyze_with(&mut MMMLSynthesizer::new(), mmml_data)
+}
+
+/// Analyze µMML bytecode with a configured synthesizer (sample bank, macro mode...)
+pub fn analyze_with(mmml: &mut MMMLSynthesizer, mmml_data: &[u8]) -> Result<SongInfo, MMMLError> {
+    mmml.is_recording_events = true;
+    mmml.load(mmml_data)?;
+    let header_size: u16 = read_pointer(mmml_data, 0).unwrap_or(0);
+
+    let mut info: SongInfo = SongInfo {
+        length: None,
+        channels: [ChannelInfo::default(); TOTAL_VOICES],
+        tempo_changes: Vec::new(),
+        macro_calls: vec![0; macro_count(header_size)],
+        frame_rate: mmml.frame_rate()
+    };
+    let mut time: SongTime = SongTime { ticks: 0, frames: 0 };
+    while let Some(frames) = mmml.next_tick()? {
+        for event in mmml.take_events() {
+            let channel: usize = match event {
+                SequencerEvent::Note { channel, .. }
+                | SequencerEvent::Sample { channel, .. }
+                | SequencerEvent::Tie { channel, .. }
+                | SequencerEvent::Rest { channel, .. }
+                | SequencerEvent::Tempo { channel, .. }
+                | SequencerEvent::LoopStart { channel, .. }
+                | SequencerEvent::Macro { channel, .. }
+                | SequencerEvent::ChannelEnd { channel } => channel
+            };
+            // Only the first pass of each channel is counted
+            if info.channels[channel].loop_length.is_some() {
+                continue;
+            }
+            let channel_info: &mut ChannelInfo = &mut info.channels[channel];
+            match event {
+                SequencerEvent::Note { .. } | SequencerEvent::Sample { .. } => channel_info.notes += 1,
+                SequencerEvent::Rest { .. } => channel_info.rests += 1,
+                SequencerEvent::Tie { .. } => {},
+                SequencerEvent::Tempo { tempo, .. } => info.tempo_changes.push(TempoChange { time, channel, tempo }),
+                SequencerEvent::LoopStart { depth, .. } => channel_info.max_loop_depth = channel_info.max_loop_depth.max(depth),
+                SequencerEvent::Macro { index, .. } => {
+                    if let Some(calls) = info.macro_calls.get_mut(index as usize) {
+                        *calls += 1;
+                    }
+                },
+                SequencerEvent::ChannelEnd { .. } => channel_info.loop_length = Some(time)
+            }
+        }
+        if mmml.has_ended() {
+            info.length = Some(time);
+            break;
+        }
+        time.ticks += 1;
+        time.frames += frames as u64;
+        if time.ticks >= MAX_TICKS {
+            break;
+        }
+    }
+    Ok(info)
+}
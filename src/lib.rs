@@ -0,0 +1,75 @@
+//! µMML player library
+//!
+//! Loads µMML sources or compiled µMML binaries and synthesizes them
+//! into 1-bit audio, either as raw samples or as a WAV file.
+use std::io::{Error, ErrorKind};
+
+use mmml_compiler::{compiler::Compiler, lexer::Lexer};
+
+pub mod bytecode;
+pub mod decompiler;
+#[cfg(feature = "playback")]
+pub mod device;
+pub mod disassembler;
+pub mod error;
+pub mod info;
+pub mod midi;
+pub mod midi_import;
+pub mod mmml_engine;
+pub mod resampler;
+pub mod sample_bank;
+pub mod sample_converter;
+pub mod sink;
+pub mod tuning;
+pub mod wav;
+pub mod validator;
+
+pub use decompiler::decompile;
+#[cfg(feature = "playback")]
+pub use device::DeviceSink;
+pub use disassembler::disassemble;
+pub use error::MMMLError;
+pub use info::{analyze, analyze_with, SongInfo};
+pub use midi::export_midi;
+pub use midi_import::import_midi;
+pub use mmml_engine::{EndCondition, HardwareClock, MixMode, MMMLSynthesizer, SequencerEvent, SongPosition, VoiceChannel, FRAME_RATE, TOTAL_VOICES};
+pub use resampler::Resampler;
+pub use sample_bank::SampleBank;
+pub use sample_converter::{convert_sample, preview_sample, read_wav, BitConversion};
+pub use sink::{render, AudioSink, NullSink, RawSink, StreamSpec};
+pub use tuning::{Temperament, Tuning};
+pub use validator::{validate, validate_for, Severity, ValidationIssue};
+pub use wav::{export_stems, export_wav, stem_path, WavFormat, WavOptions, WavSink};
+
+/// Sample rate of the PIM output (4 interleaved voices)
+pub const SAMPLE_RATE: u32 = FRAME_RATE * TOTAL_VOICES as u32; // 1-bit music demands higer rates
+
+/// Get the µMML bytecode from a µMML source or binary file content.
+///
+/// Binaries (.mbf, .mmmldata) are detected by their trailing `0xFF` end byte,
+/// anything else is compiled as µMML source code.
+pub fn get_mmml_data(data: Vec<u8>) -> Result<Vec<u8>, MMMLError> {
+    if data.last() == Some(&0xFF) {
+        let mut d: Vec<u8> = data.clone();
+        d.push(0x00);
+        return Ok(d);
+    }
+    if data.len() >= 2 && data[data.len() - 2] == 0xFF {
+        return Ok(data);
+    }
+    if let Ok(source_code) = String::from_utf8(data) {
+        eprintln!("Compiling µMML file...");
+        let mmml_data: Vec<u8> = compile_mmml(source_code)?;
+        eprintln!("Compiling complete!");
+        return Ok(mmml_data);
+    }
+    Err(MMMLError::Compile(Error::new(ErrorKind::InvalidData, "Invaild µMML file.")))
+}
+
+/// Compile µMML source code into bytecode
+pub fn compile_mmml(source_code: String) -> Result<Vec<u8>, MMMLError> {
+    let mut lexer: Lexer = Lexer::new(source_code);
+    let tokens = lexer.tokenize().map_err(MMMLError::Compile)?;
+    let mut compiler: Compiler = Compiler::new(tokens);
+    compiler.compile().map_err(MMMLError::Compile)
+}
@@ -1,73 +1,213 @@
-use std::{fs::File, io::{BufWriter, Error, ErrorKind}};
+use std::{path::{Path, PathBuf}, process::exit};
 
-use args::MMMLPlayerArgs;
-use clap::Parser;
-use hound::{SampleFormat, WavSpec, WavWriter};
-use mmml_compiler::{compiler::Compiler, lexer::Lexer};
-use mmml_engine::MMMLSynthesizer;
+use args::{MMMLPlayerArgs, MMMLPlayerCommand};
+use mmml_player::{
+    analyze_with, compile_mmml, convert_sample, decompile, disassemble, export_midi, export_stems, export_wav, get_mmml_data,
+    import_midi, preview_sample, read_wav, render, validate_for, BitConversion, EndCondition, HardwareClock, MixMode, MMMLError,
+    MMMLSynthesizer, RawSink, SampleBank, Severity, Temperament, Tuning, ValidationIssue, WavFormat, WavOptions, WavSink
+};
+
+#[cfg(feature = "playback")]
+use mmml_player::DeviceSink;
 
 mod args;
-mod mmml_engine;
+
+const USAGE_EXIT_CODE: i32 = 64; // invalid command line, apart from the error classes of `MMMLError`
 
 fn main() {
-    let args: MMMLPlayerArgs = MMMLPlayerArgs::parse();
-    
-    match std::fs::read(args.input_file.clone()) {
-        Ok(data) => play_mmml(data, args),
-        Err(err) => {
-            println!("Failed to read file:\n\t{}", err);
-        }
+    let args: MMMLPlayerArgs = MMMLPlayerArgs::try_parse_checked().unwrap_or_else(|err| {
+        // Help and version requests aren't failures
+        let code: i32 = if err.use_stderr() { USAGE_EXIT_CODE } else { 0 };
+        let _ = err.print();
+        exit(code)
+    });
+
+    let result: Result<(), MMMLError> = match &args.command {
+        Some(MMMLPlayerCommand::Disasm { input_file }) => disasm_mmml(input_file),
+        Some(MMMLPlayerCommand::Info { input_file, sample_bank }) => info_mmml(input_file, sample_bank.as_deref()),
+        Some(MMMLPlayerCommand::Decompile { input_file, output_file }) => {
+            let output_file: PathBuf = output_file.clone().unwrap_or(input_file.with_extension("mmml"));
+            decompile_mmml(input_file, &output_file)
+        },
+        Some(MMMLPlayerCommand::Midi { input_file, output_file, sample_bank }) => {
+            let output_file: PathBuf = output_file.clone().unwrap_or(input_file.with_extension("mid"));
+            midi_mmml(input_file, &output_file, sample_bank.as_deref())
+        },
+        Some(MMMLPlayerCommand::Import { input_file, output_file }) => {
+            let output_file: PathBuf = output_file.clone().unwrap_or(input_file.with_extension("mbf"));
+            import_mmml(input_file, &output_file)
+        },
+        Some(MMMLPlayerCommand::Sample { input_file, output_file, sample_bank, conversion, preview }) => {
+            let output_file: PathBuf = output_file.clone().unwrap_or(input_file.with_extension("bank"));
+            sample_mmml(input_file, &output_file, sample_bank.as_deref(), *conversion, preview.as_deref())
+        },
+        None => play_mmml(args)
+    };
+    if let Err(err) = result {
+        eprintln!("Failed to process µMML file:\n\t{}", err);
+        exit(err.exit_code());
     }
 }
 
-fn get_mmml_data(data: Vec<u8>) -> Result<Vec<u8>, Error> {
-    if data[data.len() - 1] == 0xFF {
-        let mut d: Vec<u8> = data.clone();
-        d.push(0x00);
-        return Ok(d);
+fn read_mmml_data(input_file: &Path) -> Result<Vec<u8>, MMMLError> {
+    let data: Vec<u8> = std::fs::read(input_file)?;
+    get_mmml_data(data)
+}
+
+/// Read a sample bank file, the built-in samples without one
+fn load_sample_bank(bank_file: Option<&Path>) -> Result<SampleBank, MMMLError> {
+    match bank_file {
+        Some(path) => SampleBank::load(path),
+        None => Ok(SampleBank::builtin())
     }
-    if data[data.len() - 2] == 0xFF {
-        return Ok(data);
+}
+
+/// Read the µMML data and validate it for the synthesizer playing it, printing every issue found
+fn read_checked_mmml_data(input_file: &Path, mmml: &MMMLSynthesizer) -> Result<Vec<u8>, MMMLError> {
+    let mmml_data: Vec<u8> = read_mmml_data(input_file)?;
+    let issues: Vec<ValidationIssue> = validate_for(mmml, &mmml_data);
+    for issue in &issues {
+        match issue.severity {
+            Severity::Error => eprintln!("Invalid µMML data at {}", issue),
+            Severity::Warning => eprintln!("Warning: µMML data at {}", issue)
+        }
     }
-    if let Ok(source_code) = String::from_utf8(data) {
-        println!("Compiling µMML file...");
-        let mut lexer: Lexer = Lexer::new(source_code);
-        let mut compiler: Compiler = Compiler::new(lexer.tokenize()?);
-        let mmml_data: Vec<u8> = compiler.compile()?;
-        println!("Compiling complete!");
-        return Ok(mmml_data);
+    match issues.into_iter().find(|issue| issue.severity == Severity::Error) {
+        Some(issue) => Err(issue.into()),
+        None => Ok(mmml_data)
     }
-    Err(Error::new(ErrorKind::InvalidData, "Invaild µMML file."))
 }
 
-fn play_mmml(data: Vec<u8>, args: MMMLPlayerArgs) {
-    match get_mmml_data(data) {
-        Ok(mmml_data) => {
-            let mut mmml: MMMLSynthesizer = MMMLSynthesizer::new();
-            mmml.channels[0].is_muted = args.ch1_muted;
-            mmml.channels[1].is_muted = args.ch2_muted;
-            mmml.channels[2].is_muted = args.ch3_muted;
-            mmml.channels[3].is_muted = args.ch4_muted;
-            println!("Generating samples...");
-            let samples: Vec<u8> = mmml.generate_mmml(&mmml_data);
-            println!("Samples generated!");
-
-            println!("Creating WAV file...");
-            let specs: WavSpec = WavSpec {
-                channels: 1,
-                sample_rate: 141120, // 1-bit music demands higer rates
-                bits_per_sample: 8,
-                sample_format: SampleFormat::Int
-            };
-            let mut writer: WavWriter<BufWriter<File>> = WavWriter::create(args.get_output_path(), specs).unwrap();
-            for sample in samples {
-                writer.write_sample(sample as i8).unwrap();
-            }
-            writer.finalize().unwrap();
-            println!("µMML Music sythetized successfuly!");
-        },
-        Err(err) => {
-            println!("Failed to get µMML data:\n\t{}", err);
+fn disasm_mmml(input_file: &Path) -> Result<(), MMMLError> {
+    let mmml_data: Vec<u8> = read_mmml_data(input_file)?;
+    print!("{}", disassemble(&mmml_data));
+    Ok(())
+}
+
+fn decompile_mmml(input_file: &Path, output_file: &Path) -> Result<(), MMMLError> {
+    let mmml_data: Vec<u8> = read_mmml_data(input_file)?;
+    let source_code: String = decompile(&mmml_data)?;
+
+    // Check the source gives back the same bytecode before writing it
+    let recompiled: Vec<u8> = get_mmml_data(compile_mmml(source_code.clone())?)?;
+    if recompiled != mmml_data {
+        return Err(MMMLError::DecompileMismatch);
+    }
+    std::fs::write(output_file, &source_code)?;
+    println!("µMML source written to {}, its recompiled bytecode is identical.", output_file.display());
+    Ok(())
+}
+
+fn info_mmml(input_file: &Path, bank_file: Option<&Path>) -> Result<(), MMMLError> {
+    let mut mmml: MMMLSynthesizer = MMMLSynthesizer::new();
+    mmml.sample_bank = load_sample_bank(bank_file)?;
+    let mmml_data: Vec<u8> = read_checked_mmml_data(input_file, &mmml)?;
+    print!("{}", analyze_with(&mut mmml, &mmml_data)?);
+    Ok(())
+}
+
+fn midi_mmml(input_file: &Path, output_file: &Path, bank_file: Option<&Path>) -> Result<(), MMMLError> {
+    let mut mmml: MMMLSynthesizer = MMMLSynthesizer::new();
+    mmml.sample_bank = load_sample_bank(bank_file)?;
+    let mmml_data: Vec<u8> = read_checked_mmml_data(input_file, &mmml)?;
+    export_midi(&mut mmml, &mmml_data, output_file)?;
+    println!("MIDI file written to {}", output_file.display());
+    Ok(())
+}
+
+fn import_mmml(input_file: &Path, output_file: &Path) -> Result<(), MMMLError> {
+    let midi: Vec<u8> = std::fs::read(input_file)?;
+    let mmml_data: Vec<u8> = import_midi(&midi)?;
+    std::fs::write(output_file, mmml_data)?;
+    println!("µMML binary written to {}", output_file.display());
+    Ok(())
+}
+
+fn sample_mmml(input_file: &Path, output_file: &Path, bank_file: Option<&Path>, conversion: BitConversion, preview_file: Option<&Path>) -> Result<(), MMMLError> {
+    let mut sample_bank: SampleBank = load_sample_bank(bank_file)?;
+    let (samples, sample_rate) = read_wav(input_file)?;
+    let sample: Vec<u8> = convert_sample(&samples, sample_rate, conversion)?;
+    let note: u8 = sample_bank.push(&sample)?;
+    std::fs::write(output_file, sample_bank.to_bytes())?;
+    println!("Sample {} ({} bytes) written to {}", note, sample.len(), output_file.display());
+    if let Some(preview_file) = preview_file {
+        preview_sample(&sample, &mut WavSink::new(preview_file, WavFormat::I16))?;
+        println!("Preview written to {}", preview_file.display());
+    }
+    Ok(())
+}
+
+/// Convert a duration in seconds into synthesizer frames
+fn seconds_to_frames(seconds: f64, frame_rate: f64) -> u64 {
+    (seconds.max(0.0) * frame_rate).round() as u64
+}
+
+fn play_mmml(args: MMMLPlayerArgs) -> Result<(), MMMLError> {
+    let mut mmml: MMMLSynthesizer = MMMLSynthesizer::new();
+    mmml.sample_bank = load_sample_bank(args.sample_bank.as_deref())?;
+    if let Some(cycles_per_loop) = args.cycles_per_loop {
+        mmml.hardware_clock = Some(HardwareClock { cpu_clock: args.cpu_clock, cycles_per_loop });
+    }
+    let frame_rate: f64 = mmml.frame_rate();
+    // The original note table is kept unless a tuning option is given
+    let temperament: Option<Temperament> = match &args.cents {
+        Some(path) => Some(Temperament::load(path)?),
+        None => args.temperament
+    };
+    if args.reference.is_some() || temperament.is_some() {
+        // A temperament alone keeps the pitch of the original table
+        let tuning: Tuning = Tuning {
+            reference: args.reference.unwrap_or(Tuning::original_reference(frame_rate)),
+            temperament: temperament.unwrap_or_default()
+        };
+        mmml.note_table = tuning.note_table_at(frame_rate);
+    }
+    mmml.channels[0].is_muted = args.ch1_muted;
+    mmml.channels[1].is_muted = args.ch2_muted;
+    mmml.channels[2].is_muted = args.ch3_muted;
+    mmml.channels[3].is_muted = args.ch4_muted;
+    mmml.is_stereo = args.stereo;
+    mmml.is_single_macro_level = args.single_macro_level;
+    mmml.start_position = args.start.map(|start| start.song_position(frame_rate));
+    mmml.end_condition = if let Some(loops) = args.loops {
+        EndCondition::Loops(loops)
+    } else if args.first_end {
+        EndCondition::FirstChannel
+    } else if let Some(duration) = args.duration {
+        EndCondition::Duration(seconds_to_frames(duration, frame_rate))
+    } else if let Some(end) = args.end {
+        EndCondition::Position(end.song_position(frame_rate))
+    } else {
+        EndCondition::AllChannels
+    };
+    mmml.fade_frames = seconds_to_frames(args.fade, frame_rate).min(u32::MAX as u64) as u32;
+    if args.summed {
+        mmml.mix_mode = MixMode::Summed;
+        for (channel, gain) in mmml.channels.iter_mut().zip(&args.gains) {
+            channel.gain = *gain;
         }
     }
+    let mmml_data: Vec<u8> = read_checked_mmml_data(&args.get_input_path(), &mmml)?;
+
+    // Progress goes to the standard error, which keeps the standard output clean for raw samples
+    #[cfg(feature = "playback")]
+    if args.play {
+        let mut sink: DeviceSink = DeviceSink::new()?;
+        let sample_rate: u32 = sink.sample_rate();
+        eprintln!("Playing µMML music...");
+        return render(&mut mmml, &mmml_data, &mut sink, Some(sample_rate));
+    }
+    let options: WavOptions = WavOptions { sample_rate: args.sample_rate, format: args.format };
+    if args.raw {
+        eprintln!("Generating raw samples...");
+        render(&mut mmml, &mmml_data, &mut RawSink::stdout(args.format), args.sample_rate)?;
+    } else if args.stems {
+        eprintln!("Generating samples into WAV files...");
+        export_stems(&mut mmml, &mmml_data, args.get_output_path(), &options)?;
+    } else {
+        eprintln!("Generating samples into WAV file...");
+        export_wav(&mut mmml, &mmml_data, args.get_output_path(), &options)?;
+    }
+    eprintln!("µMML Music sythetized successfuly!");
+    Ok(())
 }
@@ -0,0 +1,157 @@
+/// Standard MIDI File export
+///
+/// Drives the sequencer of `MMMLSynthesizer` without synthesis and writes
+/// the notes of every channel into a format 1 MIDI file: a tempo track
+/// followed by one track per channel, one MIDI tick per sequencer tick.
+use std::path::Path;
+
+use crate::{
+    error::MMMLError,
+    mmml_engine::{MMMLSynthesizer, SequencerEvent, MAX_TICKS, TOTAL_VOICES},
+    sample_bank::BUILTIN_SAMPLES
+};
+
+pub const DIVISION: u16 = 32;         // MIDI ticks per quarter note (a whole note is 128 ticks)
+pub const DRUM_CHANNEL: u8 = 9;       // General MIDI percussion channel
+const PULSE_PROGRAM: u8 = 80;         // General MIDI square lead
+
+// General MIDI drum notes of the samples: bwoop (low tom), beep (hi wood block), kick, snare, hi-hat
+pub const DRUM_NOTES: [u8; BUILTIN_SAMPLES] = [45, 76, 36, 38, 42];
+const OTHER_DRUM_NOTE: u8 = 39;      // hand clap, for the samples of custom banks
+
+/// Track chunk being written
+struct Track {
+    data: Vec<u8>,
+    last_tick: u64
+}
+
+impl Track {
+    fn new(name: &str) -> Self {
+        let mut track: Track = Track { data: Vec::new(), last_tick: 0 };
+        let mut meta: Vec<u8> = vec![0xFF, 0x03];
+        write_vlq(&mut meta, name.len() as u32);
+        meta.extend_from_slice(name.as_bytes());
+        track.event(0, &meta);
+        track
+    }
+
+    fn event(&mut self, tick: u64, bytes: &[u8]) {
+        write_vlq(&mut self.data, (tick - self.last_tick) as u32);
+        self.data.extend_from_slice(bytes);
+        self.last_tick = tick;
+    }
+
+    /// Close the track and wrap it in its chunk
+    fn finish(mut self, tick: u64) -> Vec<u8> {
+        self.event(tick, &[0xFF, 0x2F, 0x00]);
+        let mut chunk: Vec<u8> = b"MTrk".to_vec();
+        chunk.extend_from_slice(&(self.data.len() as u32).to_be_bytes());
+        chunk.extend_from_slice(&self.data);
+        chunk
+    }
+}
+
+/// Write a variable length quantity
+pub(crate) fn write_vlq(data: &mut Vec<u8>, value: u32) {
+    let mut bytes: Vec<u8> = vec![(value & 0x7F) as u8];
+    let mut value: u32 = value >> 7;
+    while value > 0 {
+        bytes.push(((value & 0x7F) as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.reverse();
+    data.extend_from_slice(&bytes);
+}
+
+/// MIDI note closest to a pulse voice pitch, at the frame rate of the synthesizer
+fn midi_note(period: u16, step: u16, frame_rate: f64) -> u8 {
+    let frequency: f64 = frame_rate * step as f64 / period as f64;
+    (69.0 + 12.0 * (frequency / 440.0).log2()).round().clamp(0.0, 127.0) as u8
+}
+
+/// Sequence µMML bytecode into a Standard MIDI File.
+///
+/// Muted channels are left out of the file.
+pub fn sequence_midi(mmml: &mut MMMLSynthesizer, mmml_data: &[u8]) -> Result<Vec<u8>, MMMLError> {
+    mmml.load(mmml_data)?;
+    mmml.is_recording_events = true;
+    let frame_rate: f64 = mmml.frame_rate();
+
+    let mut tempo_track: Track = Track::new("Tempo");
+    let mut tracks: Vec<Track> = (0..TOTAL_VOICES)
+        .map(|channel| Track::new(&format!("Channel {}", channel + 1)))
+        .collect();
+    let midi_channels: [u8; TOTAL_VOICES] = [0, 1, 2, DRUM_CHANNEL];
+    for (track, midi_channel) in tracks.iter_mut().zip(midi_channels).take(TOTAL_VOICES - 1) {
+        track.event(0, &[0xC0 | midi_channel, PULSE_PROGRAM]);
+    }
+
+    let mut active_notes: [Option<u8>; TOTAL_VOICES] = [None; TOTAL_VOICES];
+    let mut tick_frames: u32 = 0;
+    let mut tick: u64 = 0;
+    while let Some(frames) = mmml.next_tick()? {
+        if mmml.has_ended() {
+            // Channels restarting on the final tick are never heard
+            break;
+        }
+        if frames != tick_frames {
+            let tempo: u32 = (DIVISION as f64 * frames as f64 * 1000000.0 / frame_rate).floor().min(0xFFFFFF as f64) as u32;
+            let bytes: [u8; 4] = tempo.to_be_bytes();
+            tempo_track.event(tick, &[0xFF, 0x51, 0x03, bytes[1], bytes[2], bytes[3]]);
+            tick_frames = frames;
+        }
+
+        for event in mmml.take_events() {
+            let (channel, note, velocity): (usize, Option<u8>, u8) = match event {
+                SequencerEvent::Note { channel, period, step, volume, .. } => {
+                    // A narrower pulse sounds quieter
+                    (channel, Some(midi_note(period, step, frame_rate)), (127u8 >> volume.saturating_sub(1).min(3)).max(16))
+                },
+                SequencerEvent::Sample { channel, sample, .. } => (channel, Some(DRUM_NOTES.get(sample as usize - 1).copied().unwrap_or(OTHER_DRUM_NOTE)), 100),
+                SequencerEvent::Rest { channel, .. } => (channel, None, 0),
+                _ => continue
+            };
+            if mmml.channels[channel].is_muted {
+                continue;
+            }
+            let midi_channel: u8 = midi_channels[channel];
+            if let Some(active_note) = active_notes[channel].take() {
+                tracks[channel].event(tick, &[0x80 | midi_channel, active_note, 0]);
+            }
+            if let Some(note) = note {
+                tracks[channel].event(tick, &[0x90 | midi_channel, note, velocity]);
+                active_notes[channel] = Some(note);
+            }
+        }
+
+        tick += 1;
+        if tick >= MAX_TICKS {
+            eprintln!("Warning: The song doesn't end after {} ticks! Stopping...", MAX_TICKS);
+            break;
+        }
+    }
+    for (channel, active_note) in active_notes.iter().enumerate() {
+        if let Some(active_note) = active_note {
+            tracks[channel].event(tick, &[0x80 | midi_channels[channel], *active_note, 0]);
+        }
+    }
+    mmml.is_recording_events = false;
+
+    let mut midi: Vec<u8> = b"MThd".to_vec();
+    midi.extend_from_slice(&6u32.to_be_bytes());
+    midi.extend_from_slice(&1u16.to_be_bytes());
+    midi.extend_from_slice(&(TOTAL_VOICES as u16 + 1).to_be_bytes());
+    midi.extend_from_slice(&DIVISION.to_be_bytes());
+    midi.extend(tempo_track.finish(tick));
+    for track in tracks {
+        midi.extend(track.finish(tick));
+    }
+    Ok(midi)
+}
+
+/// Sequence µMML bytecode into a Standard MIDI File on disk
+pub fn export_midi<P: AsRef<Path>>(mmml: &mut MMMLSynthesizer, mmml_data: &[u8], path: P) -> Result<(), MMMLError> {
+    let midi: Vec<u8> = sequence_midi(mmml, mmml_data)?;
+    std::fs::write(path, midi)?;
+    Ok(())
+}
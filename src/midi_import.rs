@@ -0,0 +1,387 @@
+/// Standard MIDI File import
+///
+/// Converts a MIDI file into µMML bytecode: the three busiest melodic tracks
+/// go to the pulse voices and the percussion channel to the sampler.
+/// Notes are quantized to µMML ticks (a quarter note is 32 ticks) and
+/// written with the closest standard or dotted duration, longer notes being tied.
+use crate::{
+    bytecode::Instruction,
+    error::MMMLError,
+    midi::{DIVISION, DRUM_CHANNEL, DRUM_NOTES},
+    mmml_engine::{FRAME_RATE, NOTES, TOTAL_VOICES}
+};
+
+const DEFAULT_TEMPO: u32 = 500000; // MIDI default tempo (120 BPM) in microseconds per quarter note
+
+// Note lengths in ticks with their duration nibble, longest first
+const LENGTHS: [(u64, u8); 14] = [
+    (128, 0), (96, 8), (64, 1), (48, 9), (32, 2), (24, 10), (16, 3),
+    (12, 11), (8, 4), (6, 12), (4, 5), (3, 13), (2, 6), (1, 7),
+];
+
+/// A note of the MIDI file
+struct MidiNote {
+    start: u64,
+    end: u64,
+    key: u8,
+    velocity: u8
+}
+
+/// Notes of a track and channel of the MIDI file
+struct MidiPart {
+    channel: u8,
+    notes: Vec<MidiNote>
+}
+
+/// Content of a MIDI file
+struct MidiSong {
+    division: u16,
+    tempos: Vec<(u64, u32)>,
+    parts: Vec<MidiPart>
+}
+
+/// Sound of a channel over a span of ticks
+struct Segment {
+    start: u64,
+    end: u64,
+    /// Note (1 to 12, or percussion sample) with its octave and volume, `None` for a rest
+    note: Option<(u8, u8, u8)>
+}
+
+/// Big endian reader over the MIDI data
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], MMMLError> {
+        let bytes: &[u8] = self.data.get(self.position..self.position + length)
+            .ok_or(MMMLError::InvalidMidi("unexpected end of file"))?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, MMMLError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, MMMLError> {
+        let bytes: &[u8] = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, MMMLError> {
+        let bytes: &[u8] = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Read a variable length quantity
+    fn vlq(&mut self) -> Result<u32, MMMLError> {
+        let mut value: u32 = 0;
+        for _ in 0..4 {
+            let byte: u8 = self.byte()?;
+            value = (value << 7) | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(MMMLError::InvalidMidi("invalid variable length quantity"))
+    }
+}
+
+/// Convert a Standard MIDI File into µMML bytecode
+pub fn import_midi(midi: &[u8]) -> Result<Vec<u8>, MMMLError> {
+    let song: MidiSong = parse_midi(midi)?;
+    let to_ticks = |midi_tick: u64| (midi_tick * DIVISION as u64 + song.division as u64 / 2) / song.division as u64;
+
+    // Up to three melodic parts, the busiest first
+    let (drum_parts, mut melodic_parts): (Vec<MidiPart>, Vec<MidiPart>) = song.parts.into_iter()
+        .filter(|part| !part.notes.is_empty())
+        .partition(|part| part.channel == DRUM_CHANNEL);
+    melodic_parts.sort_by_key(|part| std::cmp::Reverse(part.notes.len()));
+    if melodic_parts.len() > TOTAL_VOICES - 1 {
+        eprintln!("Warning: Only the {} busiest melodic tracks are kept!", TOTAL_VOICES - 1);
+    }
+    let drum_notes: Vec<MidiNote> = drum_parts.into_iter().flat_map(|part| part.notes).collect();
+
+    let mut channels: Vec<Vec<Segment>> = Vec::new();
+    for v in 0..TOTAL_VOICES - 1 {
+        let notes: &[MidiNote] = melodic_parts.get(v).map(|part| part.notes.as_slice()).unwrap_or(&[]);
+        channels.push(quantize(notes, &to_ticks, |midi_note| {
+            let (note, octave) = closest_note(midi_note.key);
+            (note, octave, velocity_volume(midi_note.velocity))
+        }));
+    }
+    channels.push(quantize(&drum_notes, &to_ticks, |note| (drum_sample(note.key), 0, 0)));
+
+    // Every channel lasts as long as the song, so they all end on the same tick
+    let song_end: u64 = channels.iter()
+        .filter_map(|segments| segments.last().map(|segment| segment.end))
+        .max()
+        .unwrap_or(0)
+        .max(1);
+
+    let mut tempos: Vec<(u64, u8)> = Vec::new();
+    for (midi_tick, tempo) in song.tempos {
+        let tick: u64 = to_ticks(midi_tick).min(song_end - 1);
+        let frames: f64 = tempo as f64 * FRAME_RATE as f64 / (1000000.0 * DIVISION as f64);
+        let tempo: u8 = ((frames - 1.0) / 8.0).round().clamp(0.0, 255.0) as u8;
+        tempos.retain(|(other_tick, _)| *other_tick != tick);
+        if tempos.last().map(|(_, other_tempo)| *other_tempo) != Some(tempo) {
+            tempos.push((tick, tempo));
+        }
+    }
+
+    let mut streams: Vec<Vec<u8>> = Vec::new();
+    for (v, segments) in channels.iter().enumerate() {
+        let channel_tempos: &[(u64, u8)] = if v == 0 { &tempos } else { &[] };
+        streams.push(encode_channel(segments, song_end, channel_tempos, v == TOTAL_VOICES - 1));
+    }
+
+    // Every offset of the data, up to its end, must fit the 16-bit pointers of the player
+    let length: usize = TOTAL_VOICES * 2 + streams.iter().map(Vec::len).sum::<usize>();
+    if length > u16::MAX as usize {
+        return Err(MMMLError::InvalidMidi("song too long for µMML data"));
+    }
+    let mut mmml_data: Vec<u8> = Vec::with_capacity(length);
+    let mut pointer: usize = TOTAL_VOICES * 2;
+    for stream in &streams {
+        mmml_data.extend_from_slice(&(pointer as u16).to_be_bytes());
+        pointer += stream.len();
+    }
+    for stream in streams {
+        mmml_data.extend(stream);
+    }
+    Ok(mmml_data)
+}
+
+fn parse_midi(midi: &[u8]) -> Result<MidiSong, MMMLError> {
+    let mut reader: Reader = Reader { data: midi, position: 0 };
+    if reader.bytes(4)? != b"MThd" {
+        return Err(MMMLError::InvalidMidi("missing MThd header"));
+    }
+    let header_length: u32 = reader.u32()?;
+    let _format: u16 = reader.u16()?;
+    let track_count: u16 = reader.u16()?;
+    let division: u16 = reader.u16()?;
+    reader.bytes(header_length.saturating_sub(6) as usize)?;
+    if division & 0x8000 != 0 || division == 0 {
+        return Err(MMMLError::InvalidMidi("only ticks per quarter note time division is supported"));
+    }
+
+    let mut song: MidiSong = MidiSong { division, tempos: Vec::new(), parts: Vec::new() };
+    let mut track: u16 = 0;
+    while track < track_count && reader.position < midi.len() {
+        let id: &[u8] = reader.bytes(4)?;
+        let length: usize = reader.u32()? as usize;
+        let chunk: &[u8] = reader.bytes(length)?;
+        if id == b"MTrk" {
+            parse_track(chunk, &mut song)?;
+            track += 1;
+        }
+    }
+    song.tempos.sort_by_key(|(tick, _)| *tick);
+    if song.tempos.first().map(|(tick, _)| *tick) != Some(0) {
+        song.tempos.insert(0, (0, DEFAULT_TEMPO));
+    }
+    Ok(song)
+}
+
+fn parse_track(chunk: &[u8], song: &mut MidiSong) -> Result<(), MMMLError> {
+    let mut reader: Reader = Reader { data: chunk, position: 0 };
+    let mut parts: Vec<MidiPart> = (0..16).map(|channel| MidiPart { channel, notes: Vec::new() }).collect();
+    // Start tick and velocity of the sounding notes, per channel and key
+    let mut pending: Vec<[Option<(u64, u8)>; 128]> = vec![[None; 128]; 16];
+    let mut tick: u64 = 0;
+    let mut running_status: Option<u8> = None;
+
+    while reader.position < chunk.len() {
+        tick += reader.vlq()? as u64;
+        let mut status: u8 = reader.byte()?;
+        if status & 0x80 == 0 {
+            // Running status, the byte was the first data byte
+            reader.position -= 1;
+            status = running_status.ok_or(MMMLError::InvalidMidi("data byte without status"))?;
+        }
+        match status {
+            0xFF => {
+                let meta_type: u8 = reader.byte()?;
+                let length: usize = reader.vlq()? as usize;
+                let data: &[u8] = reader.bytes(length)?;
+                match meta_type {
+                    0x51 if length == 3 => {
+                        song.tempos.push((tick, u32::from_be_bytes([0, data[0], data[1], data[2]])));
+                    },
+                    0x2F => break,
+                    _ => {}
+                }
+            },
+            0xF0 | 0xF7 => {
+                let length: usize = reader.vlq()? as usize;
+                reader.bytes(length)?;
+            },
+            _ => {
+                running_status = Some(status);
+                let channel: usize = (status & 0x0F) as usize;
+                match status & 0xF0 {
+                    0x80 | 0x90 => {
+                        let key: u8 = reader.byte()? & 0x7F;
+                        let velocity: u8 = reader.byte()? & 0x7F;
+                        // A new note on the same key ends the previous one
+                        if let Some((start, velocity)) = pending[channel][key as usize].take() {
+                            parts[channel].notes.push(MidiNote { start, end: tick, key, velocity });
+                        }
+                        if status & 0xF0 == 0x90 && velocity > 0 {
+                            pending[channel][key as usize] = Some((tick, velocity));
+                        }
+                    },
+                    0xC0 | 0xD0 => {
+                        reader.byte()?;
+                    },
+                    _ => {
+                        reader.bytes(2)?;
+                    }
+                }
+            }
+        }
+    }
+
+    // Close the notes left sounding at the end of the track
+    for (channel, keys) in pending.iter().enumerate() {
+        for (key, note) in keys.iter().enumerate() {
+            if let Some((start, velocity)) = note {
+                parts[channel].notes.push(MidiNote { start: *start, end: tick, key: key as u8, velocity: *velocity });
+            }
+        }
+    }
+    song.parts.extend(parts.into_iter().filter(|part| !part.notes.is_empty()));
+    Ok(())
+}
+
+/// Quantize notes into monophonic segments, a new note cutting the previous one
+fn quantize(notes: &[MidiNote], to_ticks: &dyn Fn(u64) -> u64, describe: impl Fn(&MidiNote) -> (u8, u8, u8)) -> Vec<Segment> {
+    let mut sorted: Vec<&MidiNote> = notes.iter().collect();
+    // Highest note first on chords
+    sorted.sort_by(|a, b| a.start.cmp(&b.start).then(b.key.cmp(&a.key)));
+
+    let mut segments: Vec<Segment> = Vec::new();
+    for note in sorted {
+        let start: u64 = to_ticks(note.start);
+        let end: u64 = to_ticks(note.end);
+        if end <= start {
+            continue;
+        }
+        if let Some(last) = segments.last_mut() {
+            if last.start == start {
+                continue;
+            }
+            last.end = last.end.min(start);
+        }
+        segments.push(Segment { start, end, note: Some(describe(note)) });
+    }
+    segments
+}
+
+/// Note (1 to 12) and octave nibble sounding the closest to a MIDI key
+fn closest_note(key: u8) -> (u8, u8) {
+    let mut closest: (u8, u8) = (1, 0);
+    let mut closest_distance: f64 = f64::MAX;
+    for octave in 0..=6u8 {
+        for note in 1..=12u8 {
+            let frequency: f64 = FRAME_RATE as f64 * (2u32 << octave) as f64 / NOTES[note as usize] as f64;
+            let distance: f64 = (69.0 + 12.0 * (frequency / 440.0).log2() - key as f64).abs();
+            if distance < closest_distance {
+                closest = (note, octave);
+                closest_distance = distance;
+            }
+        }
+    }
+    closest
+}
+
+/// Volume (pulse width) of a MIDI velocity, softer notes get narrower pulses
+fn velocity_volume(velocity: u8) -> u8 {
+    match velocity {
+        96.. => 1,
+        64..=95 => 2,
+        32..=63 => 3,
+        _ => 4
+    }
+}
+
+/// Percussion sample of a General MIDI drum note
+fn drum_sample(key: u8) -> u8 {
+    if let Some(index) = DRUM_NOTES.iter().position(|note| *note == key) {
+        return index as u8 + 1;
+    }
+    match key {
+        35 | 36 => 3,                          // kick
+        37..=40 => 4,                          // snare
+        41 | 43 | 45 | 47 | 48 | 50 => 1,      // toms: bwoop
+        42 | 44 | 46 | 49 | 51..=59 => 5,      // hi-hats and cymbals
+        _ => 2                                 // beep
+    }
+}
+
+/// Encode the segments of a channel, filling the gaps with rests
+fn encode_channel(segments: &[Segment], song_end: u64, tempos: &[(u64, u8)], is_sampler: bool) -> Vec<u8> {
+    let mut data: Vec<u8> = Vec::new();
+    let mut octave: Option<u8> = None;
+    let mut volume: Option<u8> = None;
+
+    // Segments and rests covering the whole song, split on tempo changes
+    let mut spans: Vec<Segment> = Vec::new();
+    let mut cursor: u64 = 0;
+    for segment in segments.iter().chain(std::iter::once(&Segment { start: song_end, end: song_end, note: None })) {
+        if segment.start > cursor {
+            spans.push(Segment { start: cursor, end: segment.start, note: None });
+        }
+        if segment.end > segment.start {
+            spans.push(Segment { start: segment.start, end: segment.end, note: segment.note });
+        }
+        cursor = segment.end.max(cursor);
+    }
+
+    for span in spans {
+        let mut points: Vec<u64> = vec![span.start];
+        points.extend(tempos.iter().map(|(tick, _)| *tick).filter(|tick| *tick > span.start && *tick < span.end));
+        points.push(span.end);
+        for (index, window) in points.windows(2).enumerate() {
+            if let Some((_, tempo)) = tempos.iter().find(|(tick, _)| *tick == window[0]) {
+                Instruction::Tempo(*tempo).encode(&mut data);
+            }
+            let mut length: u64 = window[1] - window[0];
+            let mut is_continued: bool = index > 0;
+            while length > 0 {
+                let (ticks, duration) = *LENGTHS.iter().find(|(ticks, _)| *ticks <= length).unwrap_or(&LENGTHS[13]);
+                match span.note {
+                    Some((sample, _, _)) if is_sampler => {
+                        // Percussion samples are one shots, the rest of the span is silent
+                        let sample: u8 = if is_continued { 0 } else { sample };
+                        Instruction::Note { note: sample, duration }.encode(&mut data);
+                    },
+                    Some((note, note_octave, note_volume)) => {
+                        if octave != Some(note_octave) {
+                            Instruction::Octave(note_octave).encode(&mut data);
+                            octave = Some(note_octave);
+                        }
+                        if volume != Some(note_volume) {
+                            Instruction::Volume(note_volume).encode(&mut data);
+                            volume = Some(note_volume);
+                        }
+                        if is_continued {
+                            Instruction::Tie.encode(&mut data);
+                        }
+                        Instruction::Note { note, duration }.encode(&mut data);
+                    },
+                    None => Instruction::Note { note: 0, duration }.encode(&mut data)
+                }
+                is_continued = true;
+                length -= ticks;
+            }
+        }
+    }
+    Instruction::ChannelEnd.encode(&mut data);
+    data
+}
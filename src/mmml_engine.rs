@@ -1,81 +1,151 @@
 /// MMML Synthesizer in Rust
 /// 
 /// This module handles sequencing from .mmmldata files.
-/// It generates 1-bit (stored as 8-bit) mono audio samples.
+/// It generates 1-bit (stored as 8-bit) mono audio samples,
+/// or interleaved left / right samples when rendering in stereo.
+use crate::{
+    bytecode::{channel_pointer_offset, macro_count, macro_pointer_offset, note_length, read_pointer, Instruction},
+    error::MMMLError,
+    sample_bank::SampleBank
+};
+
 // Note table (plus an initial 'wasted' entry for rests)
-const NOTES: [u16; 13] = [
+pub const NOTES: [u16; 13] = [
     // the rest command is technically note 0 and thus requires a frequency
     255,
     // one octave of notes, equal temperament
     1644, 1551, 1464, 1382, 1305, 1231, 1162, 1097, 1035, 977, 922, 871,
 ];
 
-// Location of individual samples in sample array
-const SAMPLE_INDICIES: [u8; 6] = [0, 19, 34, 74, 118, 126];
-
-// Raw PWM sample data
-const SAMPLES: [u8; SAMPLE_LENGTH] = [
-    // bwoop (0)
-    0b10101010, 0b10110110, 0b10000111, 0b11111000,
-    0b10000100, 0b00110111, 0b11101000, 0b11000001,
-    0b00000111, 0b00111101, 0b11111000, 0b11100000,
-    0b10010001, 0b10000111, 0b00000111, 0b00001111,
-    0b00001111, 0b00011011, 0b00011110,
-    // beep (19)
-    0b10101010, 0b00101010, 0b00110011, 0b00110011,
-    0b00110011, 0b00110011, 0b00110011, 0b11001101,
-    0b11001100, 0b11001100, 0b11001100, 0b10101100,
-    0b10011001, 0b00110001, 0b00110011,
-    // kick (34)
-    0b10010101, 0b10110010, 0b00000000, 0b11100011,
-    0b11110000, 0b00000000, 0b11111111, 0b00000000,
-    0b11111110, 0b00000000, 0b00000000, 0b00000000,
-    0b11111111, 0b11111111, 0b11111111, 0b00100101,
-    0b00000000, 0b00000000, 0b00000000, 0b00000000,
-    0b11111111, 0b11110111, 0b11111111, 0b11111111,
-    0b11111111, 0b10111111, 0b00010010, 0b00000000,
-    0b10000000, 0b00000000, 0b00000000, 0b00000000,
-    0b00000000, 0b11101110, 0b11111111, 0b11111111,
-    0b11111111, 0b11110111, 0b11111111, 0b11111110,
-    // snare (74)
-    0b10011010, 0b10011010, 0b10101010, 0b10010110,
-    0b01110100, 0b10010101, 0b10001010, 0b11011110,
-    0b01110100, 0b10100000, 0b11110111, 0b00100101,
-    0b01110100, 0b01101000, 0b11111111, 0b01011011,
-    0b01000001, 0b10000000, 0b11010100, 0b11111101,
-    0b11011110, 0b00010010, 0b00000100, 0b00100100,
-    0b11101101, 0b11111011, 0b01011011, 0b00100101,
-    0b00000100, 0b10010001, 0b01101010, 0b11011111,
-    0b01110111, 0b00010101, 0b00000010, 0b00100010,
-    0b11010101, 0b01111010, 0b11101111, 0b10110110,
-    0b00100100, 0b10000100, 0b10100100, 0b11011010,
-    // hi-hat (118)
-    0b10011010, 0b01110100, 0b11010100, 0b00110011,
-    0b00110011, 0b11101000, 0b11101000, 0b01010101,
-    0b01010101,
-    // end (126)
+// Instrument table, selected per channel by the instrument command
+const INSTRUMENTS: [Instrument; 6] = [
+    // plain pulse, duty cycle set by the channel volume (default)
+    Instrument { duty: &[], envelope: &[], sweep: 0 },
+    // pluck, the pulse narrows as the note decays
+    Instrument { duty: &[], envelope: &[0, 0, 1, 1, 2, 2, 3, 3, 4], sweep: 0 },
+    // pulse width modulation
+    Instrument { duty: &[1, 1, 2, 2, 3, 3, 2, 2], envelope: &[], sweep: 0 },
+    // thin lead with a short attack
+    Instrument { duty: &[1, 2, 3], envelope: &[], sweep: 0 },
+    // tom, quick pitch drop
+    Instrument { duty: &[], envelope: &[0, 0, 1, 1, 2, 3], sweep: 48 },
+    // laser, pitch rise
+    Instrument { duty: &[], envelope: &[], sweep: -16 },
 ];
 
 const SAMPLE_SPEED: u8 = 3;      // the sampler playback rate
-const SAMPLE_LENGTH: usize = 127; // the length of the sample array
-const MAXLOOPS: usize = 5;        // the maximum number of nested loops
-const TOTAL_VOICES: usize = 4;    // total number of 1-bit voices to synthesize
+pub const SAMPLER_RATE: u32 = FRAME_RATE / (SAMPLE_SPEED as u32 + 1); // the rate sample bits are played at
+pub const MAXLOOPS: usize = 5;    // the maximum number of nested loops
+pub const MAX_MACRO_DEPTH: usize = 8; // the maximum number of nested macro calls
+pub const TOTAL_VOICES: usize = 4; // total number of 1-bit voices to synthesize
+pub const FRAME_RATE: u32 = 35280; // the rate voices are synthesized at (one sample each)
 const AMPLITUDE: u8 = 127;        // waveform high position (maximum from DC zero is 127)
-const DC_OFFSET: u8 = 0;        // waveform low position (127 is DC zero)
+pub const DC_OFFSET: u8 = 0;    // waveform low position (127 is DC zero)
+const MAX_BUFFER_SIZE: usize = 1073741824; // sample limit of generate_mmml (1GB)
+pub(crate) const MAX_TICKS: u64 = 1 << 20; // tick limit of the analyses of songs that never end
+
+/// Output sample of a summed mix level, the full mix reaching the same amplitude as a single PIM voice
+fn summed_sample(level: u32) -> u8 {
+    (level / TOTAL_VOICES as u32).min(AMPLITUDE as u32) as u8 + DC_OFFSET
+}
+
+/// How the voices are mixed into the output samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MixMode {
+    /// Pulse-interleaving modulation: one sample per voice per frame, as the hardware does
+    #[default]
+    Pim,
+    /// The voices are summed into one sample per frame using the channel gains
+    Summed
+}
+
+/// Timing of the microcontroller running the original player, which
+/// synthesizes one frame per iteration of its main loop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HardwareClock {
+    /// CPU clock in Hz
+    pub cpu_clock: u32,
+    /// CPU cycles taken by an iteration of the main loop
+    pub cycles_per_loop: u32
+}
+
+impl HardwareClock {
+    /// Frames synthesized per second
+    pub fn frame_rate(&self) -> f64 {
+        self.cpu_clock as f64 / self.cycles_per_loop.max(1) as f64
+    }
+}
+
+/// When the song stops playing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EndCondition {
+    /// Every channel reaches its end on the same tick, as the original player
+    #[default]
+    AllChannels,
+    /// Every channel played through its data the given number of times
+    Loops(u32),
+    /// Any channel reaches its end
+    FirstChannel,
+    /// The song reached the given position
+    Position(SongPosition),
+    /// The given number of frames were played from the start position
+    Duration(u64)
+}
+
+/// Position in a song, from its start
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SongPosition {
+    /// Number of frames played
+    Frames(u64),
+    /// Start of the given tick (the first tick is 0)
+    Ticks(u64)
+}
 
-const LOOP_START: u8 = 0x00;
-const LOOP_END: u8 = 0x01;
-const MACRO: u8 = 0x02;
-const TEMPO: u8 = 0x03;
-const CHANNEL_END: u8 = 0x0F;
-const OCTAVE: u8 = 0x0D;
-const VOLUME: u8 = 0x0E;
+/// Event of the sequencer, recorded while `is_recording_events` is set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequencerEvent {
+    /// A note starts on a pulse voice, sounding at `FRAME_RATE * step / period` Hz
+    Note { channel: usize, period: u16, step: u16, volume: u8, length: u8 },
+    /// A percussion sample starts on the sampler channel
+    Sample { channel: usize, sample: u8, length: u8 },
+    /// The current note is extended by a tie
+    Tie { channel: usize, length: u8 },
+    Rest { channel: usize, length: u8 },
+    Tempo { channel: usize, tempo: u8 },
+    /// A loop starts, `depth` being the number of loops now active on the channel
+    LoopStart { channel: usize, count: u8, depth: u8 },
+    /// A macro is called
+    Macro { channel: usize, index: u8 },
+    /// A channel reached its end and restarts from the beginning
+    ChannelEnd { channel: usize },
+}
+
+/// Duty and pitch behavior of a pulse voice
+struct Instrument {
+    duty: &'static [u8],     // looped duty cycle sequence (empty: use the channel volume)
+    envelope: &'static [u8], // duty narrowing over time, the last step is held
+    sweep: i8,               // frequency change per tick (positive lowers the pitch)
+}
+
+impl Instrument {
+    /// Whether the instrument changes the waveform after the note started.
+    fn is_static(&self) -> bool {
+        self.duty.len() <= 1 && self.envelope.len() <= 1 && self.sweep == 0
+    }
+}
 
 /// Stores the state for a single voice channel
 pub struct VoiceChannel {
     output: u8,
     octave: u8,
     volume: u8,
+    transpose: i8,
+    octave_shift: i8,
+    tie_pending: bool,
+    pan: i8,
+    instrument: u8,
+    instrument_position: u8,
+    is_resting: bool,
     length: u8,
     loops_active: u8,
     frequency: u16,
@@ -84,8 +154,11 @@ pub struct VoiceChannel {
     pitch_counter: u16,
     loop_duration: [u16; MAXLOOPS],
     loop_point: [u16; MAXLOOPS],
-    pointer_location: u16,
-    pub is_muted: bool
+    call_stack: [u16; MAX_MACRO_DEPTH], // return addresses of the running macros
+    call_depth: u8,
+    pub is_muted: bool,
+    /// Gain (out of 127) of the channel in the summed mix
+    pub gain: u8
 }
 
 impl VoiceChannel {
@@ -94,6 +167,13 @@ impl VoiceChannel {
             output: 0,
             octave: 3,    // default octave: o3
             volume: 1,    // default volume: 50% pulse wave
+            transpose: 0, // default transpose: none
+            octave_shift: 0,
+            tie_pending: false,
+            pan: 0,       // default panning: center
+            instrument: 0, // default instrument: plain pulse
+            instrument_position: 0,
+            is_resting: true,
             length: 0,
             loops_active: 0,
             frequency: 255, // random frequency (won't ever be sounded)
@@ -102,18 +182,70 @@ impl VoiceChannel {
             pitch_counter: 0,
             loop_duration: [0; MAXLOOPS],
             loop_point: [0; MAXLOOPS],
-            pointer_location: 0,
-            is_muted: false
+            call_stack: [0; MAX_MACRO_DEPTH],
+            call_depth: 0,
+            is_muted: false,
+            gain: 127
+        }
+    }
+
+    /// Pitch counter increment for the current note, with the octave carried over
+    /// by transposition applied on top of the channel octave.
+    fn pitch_step(&self) -> u16 {
+        if self.octave_shift >= 0 {
+            ((self.octave as u32) << self.octave_shift).min(0x7FFF) as u16
+        } else {
+            ((self.octave as u16) >> -self.octave_shift).max(1)
         }
     }
+
+    /// Start a new note, restarting the channel instrument.
+    fn start_note(&mut self, frequency: u16) {
+        self.frequency = frequency;
+        self.instrument_position = 0;
+        self.is_resting = false;
+        self.waveform = self.duty_waveform();
+    }
+
+    /// Calculate the waveform duty cycle by dividing the frequency by powers of two.
+    fn duty_waveform(&self) -> u16 {
+        let instrument = &INSTRUMENTS[self.instrument as usize];
+        let position = self.instrument_position as usize;
+        let duty = match instrument.duty.len() {
+            0 => self.volume,
+            length => instrument.duty[position % length],
+        };
+        let envelope = match instrument.envelope.len() {
+            0 => 0,
+            length => instrument.envelope[position.min(length - 1)],
+        };
+        self.frequency.checked_shr((duty + envelope) as u32).unwrap_or(0)
+    }
+
+    /// Advance the channel instrument by one tick.
+    fn update_instrument(&mut self) {
+        let instrument = &INSTRUMENTS[self.instrument as usize];
+        if self.is_resting || instrument.is_static() {
+            return;
+        }
+        self.instrument_position = self.instrument_position.saturating_add(1);
+        self.frequency = (self.frequency as i32 + instrument.sweep as i32).clamp(16, 0x7FFF) as u16;
+        self.waveform = self.duty_waveform();
+    }
+
+    /// Left and right gains (out of 127) for the channel pan position.
+    fn pan_gains(&self) -> (u16, u16) {
+        let pan = self.pan.max(-127) as i16;
+        ((127 - pan.max(0)) as u16, (127 + pan.min(0)) as u16)
+    }
 }
 
 /// Sampler state for percussion samples
 struct Sampler {
-    current_byte: u8,
+    current_byte: u16,
     current_bit: u8,
     sample_counter: u8,
-    current_sample: u8,
+    current_sample: u16,
 }
 
 impl Sampler {
@@ -130,10 +262,50 @@ impl Sampler {
 /// Main synthesizer state
 pub struct MMMLSynthesizer {
     pub channels: [VoiceChannel; TOTAL_VOICES],
+    /// Output interleaved left / right samples using the channels panning
+    pub is_stereo: bool,
+    pub mix_mode: MixMode,
+    /// Keep a single macro return address like the original player,
+    /// a macro called from a macro then never returns to its caller
+    pub is_single_macro_level: bool,
+    /// Position the song starts playing from, see `fast_forward`
+    pub start_position: Option<SongPosition>,
+    pub end_condition: EndCondition,
+    /// Length in frames of the fade out played once the end condition is met
+    pub fade_frames: u32,
+    /// Percussion samples of the sampler channel
+    pub sample_bank: SampleBank,
+    /// Timer periods of the notes, `NOTES` by default (see `Tuning`)
+    pub note_table: [u16; 13],
+    /// Play at the rate of a microcontroller clock instead of `FRAME_RATE`
+    pub hardware_clock: Option<HardwareClock>,
+    /// Record the sequencer events, see `take_events`
+    pub is_recording_events: bool,
+    events: Vec<SequencerEvent>,
     sampler: Sampler,
     tick_counter: u16,
     tick_speed: u16,
     header_size: u16,
+    mmml_source: Vec<u8>,
+    frame: [u8; TOTAL_VOICES * 2],
+    /// Samples of every voice played alone, for the summed mix stems
+    voice_frames: [[u8; 2]; TOTAL_VOICES],
+    frame_length: usize,
+    frame_position: usize,
+    frame_count: u64,
+    /// Frame the samples started from, after the fast forward to the start position
+    start_frame: u64,
+    tick_count: u64,
+    passes: [u32; TOTAL_VOICES],
+    fade_position: Option<u32>,
+    has_ended: bool,
+    error: Option<MMMLError>,
+}
+
+impl Default for MMMLSynthesizer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MMMLSynthesizer {
@@ -145,242 +317,652 @@ impl MMMLSynthesizer {
                 VoiceChannel::new(),
                 VoiceChannel::new()
             ],
+            is_stereo: false,
+            mix_mode: MixMode::Pim,
+            is_single_macro_level: false,
+            start_position: None,
+            end_condition: EndCondition::AllChannels,
+            fade_frames: 0,
+            sample_bank: SampleBank::builtin(),
+            note_table: NOTES,
+            hardware_clock: None,
+            is_recording_events: false,
+            events: Vec::new(),
             sampler: Sampler::new(),
             tick_counter: 0,
             tick_speed: 0,
             header_size: 0,
+            mmml_source: Vec::new(),
+            frame: [0; TOTAL_VOICES * 2],
+            voice_frames: [[DC_OFFSET; 2]; TOTAL_VOICES],
+            frame_length: 0,
+            frame_position: 0,
+            frame_count: 0,
+            start_frame: 0,
+            tick_count: 0,
+            passes: [0; TOTAL_VOICES],
+            fade_position: None,
+            has_ended: true,
+            error: None,
         }
     }
 
     /// Initialize the synthesizer with MMML data
-    fn initialize(&mut self, mmml_source: &[u8]) {
+    fn initialize(&mut self) -> Result<(), MMMLError> {
         for i in 0..TOTAL_VOICES {
-            self.channels[i].data_pointer = ((mmml_source[i * 2] as u16) << 8) | (mmml_source[i * 2 + 1] as u16);
+            self.channels[i].data_pointer = self.read_pointer(channel_pointer_offset(i), i)?;
         }
         self.header_size = self.channels[0].data_pointer;
+        Ok(())
     }
 
-    /// Generate audio samples from MMML data
-    pub fn generate_mmml(&mut self, mmml_source: &[u8]) -> Vec<u8> {
-
-        self.initialize(mmml_source);
-
-        let mut result: Vec<u8> = Vec::new();
-
-        loop {
-            /**********************
-             *  Synthesizer Code  *
-             **********************/
-
-            // Sampler (channel D) code
-            if self.sampler.sample_counter == 0 {
-                if self.sampler.current_byte < self.sampler.current_sample - 1 && (self.sampler.current_byte as usize) < SAMPLE_LENGTH {
-                    // Read individual bits from the sample array
-                    self.channels[TOTAL_VOICES - 1].output = 
-                        ((SAMPLES[self.sampler.current_byte as usize] >> self.sampler.current_bit) & 1) as u8;
-                    self.sampler.current_bit += 1;
-                } else {
-                    // Silence the channel when the sample is over
-                    self.channels[TOTAL_VOICES - 1].output = 0;
+    /// Load MMML data and restart the song from the beginning (or the start position)
+    pub fn load(&mut self, mmml_source: &[u8]) -> Result<(), MMMLError> {
+        self.mmml_source = mmml_source.to_vec();
+        // Reset the playback state, keeping the channels configuration
+        for channel in self.channels.iter_mut() {
+            *channel = VoiceChannel { is_muted: channel.is_muted, gain: channel.gain, ..VoiceChannel::new() };
+        }
+        self.sampler = Sampler::new();
+        self.events.clear();
+        self.tick_speed = 0;
+        self.tick_counter = 0;
+        self.frame_length = 0;
+        self.frame_position = 0;
+        self.frame_count = 0;
+        self.start_frame = 0;
+        self.tick_count = 0;
+        self.passes = [0; TOTAL_VOICES];
+        self.fade_position = None;
+        self.error = None;
+        self.has_ended = true;
+        self.initialize()?;
+        self.has_ended = false;
+        if let Some(position) = self.start_position {
+            self.fast_forward(position)?;
+            self.events.clear();
+            // Nothing would be played from there
+            if self.has_ended || self.fade_position.is_some() {
+                return Err(MMMLError::InvalidPosition("start position past the end of the song"));
+            }
+            if let EndCondition::Position(end) = self.end_condition {
+                if self.has_reached(end) {
+                    return Err(MMMLError::InvalidPosition("end position at or before the start position"));
                 }
+            }
+        }
+        Ok(())
+    }
+
+    /// Generate audio samples from MMML data
+    pub fn generate_mmml(&mut self, mmml_source: &[u8]) -> Result<Vec<u8>, MMMLError> {
+        self.load(mmml_source)?;
+        let result: Vec<u8> = self.by_ref().take(MAX_BUFFER_SIZE).collect();
+        if let Some(err) = self.take_error() {
+            return Err(err);
+        }
+        if result.len() >= MAX_BUFFER_SIZE {
+            eprintln!("Error: Buffer over 1GB! Abort!");
+        }
+        Ok(result)
+    }
+
+    /// Fill the buffer with the next samples of the loaded song.
+    ///
+    /// Returns the number of samples written, which is less than the buffer
+    /// length only once the song has ended.
+    pub fn fill(&mut self, buffer: &mut [u8]) -> Result<usize, MMMLError> {
+        let mut count: usize = 0;
+        for (slot, sample) in buffer.iter_mut().zip(self.by_ref()) {
+            *slot = sample;
+            count += 1;
+        }
+        match self.take_error() {
+            Some(err) => Err(err),
+            None => Ok(count)
+        }
+    }
+
+    /// Number of frames synthesized per second, pitch and tempo follow it
+    pub fn frame_rate(&self) -> f64 {
+        match self.hardware_clock {
+            Some(clock) => clock.frame_rate(),
+            None => FRAME_RATE as f64
+        }
+    }
+
+    /// Number of samples per second per output channel for the current mix mode,
+    /// rounded to the nearest Hz with a hardware clock
+    pub fn sample_rate(&self) -> u32 {
+        let samples_per_frame: f64 = match self.mix_mode {
+            MixMode::Pim => TOTAL_VOICES as f64,
+            MixMode::Summed => 1.0
+        };
+        (self.frame_rate() * samples_per_frame).round().max(1.0) as u32
+    }
+
+    /// Take the error that stopped the sample iterator, if any
+    pub fn take_error(&mut self) -> Option<MMMLError> {
+        self.error.take()
+    }
+
+    /// Read a big endian pointer of the MMML data
+    fn read_pointer(&self, offset: usize, channel: usize) -> Result<u16, MMMLError> {
+        read_pointer(&self.mmml_source, offset)
+            .ok_or(MMMLError::malformed(offset, channel, "unexpected end of data"))
+    }
+
+    /// Advance the sequencer by one tick without synthesizing any sample.
+    ///
+    /// Returns the number of frames until the next tick,
+    /// or `None` once the song has ended.
+    pub fn next_tick(&mut self) -> Result<Option<u32>, MMMLError> {
+        if self.has_ended {
+            return Ok(None);
+        }
+        let frames: u32 = self.tick_speed as u32 + 1;
+        self.tick_counter = self.tick_speed;
+        if let Err(err) = self.process_tick() {
+            self.has_ended = true;
+            return Err(err);
+        }
+        self.advance_frames(frames);
+        self.check_end_position();
+        Ok(Some(frames))
+    }
+
+    /// Whether the song has ended (or failed)
+    pub fn has_ended(&self) -> bool {
+        self.has_ended
+    }
+
+    /// Samples of every voice played alone at the position of the last sample
+    /// of the iterator, as the summed mix would output them with the other
+    /// channels muted. Only updated in summed mode, PIM voices take turns instead.
+    pub fn voice_samples(&self) -> [u8; TOTAL_VOICES] {
+        let position: usize = self.frame_position.saturating_sub(1).min(1);
+        self.voice_frames.map(|samples| samples[position])
+    }
+
+    /// Take the sequencer events recorded so far
+    pub fn take_events(&mut self) -> Vec<SequencerEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Stop the song, or start the fade out if any.
+    ///
+    /// Returns whether the song stopped right away.
+    fn finish(&mut self) -> bool {
+        if self.fade_position.is_some() {
+            return false;
+        }
+        if self.fade_frames == 0 {
+            self.has_ended = true;
+            return true;
+        }
+        self.fade_position = Some(0);
+        false
+    }
+
+    /// Count the frames played, ending the song after the fade out
+    fn advance_frames(&mut self, frames: u32) {
+        self.frame_count += frames as u64;
+        if let Some(position) = self.fade_position.as_mut() {
+            *position += frames;
+            if *position >= self.fade_frames {
+                self.has_ended = true;
+            }
+        }
+    }
 
-                // Move to the next byte on bit pointer overflow
-                if self.sampler.current_bit > 7 {
-                    self.sampler.current_byte += 1;
-                    self.sampler.current_bit = 0;
+    /// End the song once the position or duration of the end condition is reached
+    fn check_end_position(&mut self) {
+        let has_reached_end: bool = match self.end_condition {
+            EndCondition::Position(position) => self.has_reached(position),
+            EndCondition::Duration(frames) => self.frame_count - self.start_frame >= frames,
+            _ => false
+        };
+        if has_reached_end {
+            self.finish();
+        }
+    }
+
+    /// Whether the song reached a position
+    fn has_reached(&self, position: SongPosition) -> bool {
+        match position {
+            SongPosition::Frames(frames) => self.frame_count >= frames,
+            // A tick starts sounding once it was processed
+            SongPosition::Ticks(tick) => self.tick_count > tick
+        }
+    }
+
+    /// Advance the loaded song to a position without synthesizing any sample.
+    ///
+    /// The samples produced next start at this position, which is where the
+    /// duration of `EndCondition::Duration` is measured from. The end position
+    /// isn't checked on the way.
+    pub fn fast_forward(&mut self, position: SongPosition) -> Result<(), MMMLError> {
+        self.frame_length = 0;
+        self.frame_position = 0;
+        while !self.has_ended && !self.has_reached(position) {
+            // Skip to the next tick, or to the position if it comes first
+            let mut frames: u32 = self.tick_counter as u32 + 1;
+            if let SongPosition::Frames(target) = position {
+                frames = frames.min((target - self.frame_count) as u32);
+            }
+            self.skip_frames(frames);
+            self.advance_frames(frames);
+            if self.has_ended {
+                break;
+            }
+            if frames == self.tick_counter as u32 + 1 {
+                self.tick_counter = self.tick_speed;
+                if let Err(err) = self.process_tick() {
+                    self.has_ended = true;
+                    return Err(err);
                 }
-                self.sampler.sample_counter = SAMPLE_SPEED;
             } else {
-                self.sampler.sample_counter -= 1;
+                self.tick_counter -= frames as u16;
+            }
+        }
+        self.start_frame = self.frame_count;
+        Ok(())
+    }
+
+    fn record(&mut self, event: SequencerEvent) {
+        if self.is_recording_events {
+            self.events.push(event);
+        }
+    }
+
+    /// Process a note (or a rest when 0) of a channel
+    fn play_note(&mut self, v: usize, note: u8, duration: u8, data_ptr: usize) -> Result<(), MMMLError> {
+        // Note duration value
+        let length: u8 = note_length(duration);
+        self.channels[v].length = length;
+
+        if note != 0 && self.channels[v].tie_pending {
+            // Tied note: keep the running pitch, duty and sample going
+            self.record(SequencerEvent::Tie { channel: v, length });
+        } else if note != 0 {
+            if v < TOTAL_VOICES - 1 {
+                /* Transpose the note, carrying into the next / previous octave
+                 * when it leaves the 12-note table. */
+                let note = (note as i16 - 1) + self.channels[v].transpose as i16;
+                self.channels[v].octave_shift = note.div_euclid(12) as i8;
+                let buffer4 = self.note_table[(note.rem_euclid(12) + 1) as usize];
+                self.channels[v].start_note(buffer4);
+                self.record(SequencerEvent::Note {
+                    channel: v,
+                    period: buffer4,
+                    step: self.channels[v].pitch_step(),
+                    volume: self.channels[v].volume,
+                    length
+                });
+            } else if let Some((start, end)) = self.sample_bank.range(note) {
+                // Reset the sampler
+                self.sampler.current_bit = 0;
+                self.sampler.current_byte = start;
+                self.sampler.current_sample = end;
+                self.record(SequencerEvent::Sample { channel: v, sample: note, length });
+            } else {
+                return Err(MMMLError::malformed(data_ptr, v, "unknown percussion sample"));
             }
+        } else {
+            // Rest
+            self.channels[v].waveform = 0;
+            self.channels[v].is_resting = true;
+            self.record(SequencerEvent::Rest { channel: v, length });
+        }
+        self.channels[v].tie_pending = false;
+        Ok(())
+    }
 
-            // Calculate pulse values
-            for v in 0..TOTAL_VOICES - 1 {
-                self.channels[v].pitch_counter += self.channels[v].octave as u16;
-                if self.channels[v].pitch_counter >= self.channels[v].frequency {
-                    self.channels[v].pitch_counter = self.channels[v].pitch_counter - self.channels[v].frequency;
-                }
-                if self.channels[v].pitch_counter <= self.channels[v].waveform {
-                    self.channels[v].output = 1;
-                }
-                if self.channels[v].pitch_counter >= self.channels[v].waveform {
-                    self.channels[v].output = 0;
-                }
+    /// Apply the fade out to a sample
+    fn fade(&self, sample: u8) -> u8 {
+        match self.fade_position {
+            Some(position) => {
+                let remaining: u32 = self.fade_frames - position;
+                DC_OFFSET + ((sample - DC_OFFSET) as u32 * remaining / self.fade_frames) as u8
+            },
+            None => sample
+        }
+    }
+
+    /// Append a sample to the current frame
+    fn push_sample(&mut self, sample: u8) {
+        self.frame[self.frame_length] = self.fade(sample);
+        self.frame_length += 1;
+    }
+
+    /// Whether the sampler is still reading the current sample
+    fn is_sampler_playing(&self) -> bool {
+        self.sampler.current_byte < self.sampler.current_sample.wrapping_sub(1) && (self.sampler.current_byte as usize) < self.sample_bank.len()
+    }
+
+    /// Sampler (channel D) code
+    fn step_sampler(&mut self) {
+        if self.sampler.sample_counter == 0 {
+            if self.is_sampler_playing() {
+                // Read individual bits from the sample array
+                self.channels[TOTAL_VOICES - 1].output = 
+                    (self.sample_bank.byte(self.sampler.current_byte) >> self.sampler.current_bit) & 1;
+                self.sampler.current_bit += 1;
+            } else {
+                // Silence the channel when the sample is over
+                self.channels[TOTAL_VOICES - 1].output = 0;
             }
 
-            // Output and interleave samples using PIM
-            for v in 0..TOTAL_VOICES {
-                if !self.channels[v].is_muted {
-                    result.push((self.channels[v].output * AMPLITUDE) + DC_OFFSET);
-                } else {
-                    result.push(DC_OFFSET);
-                }
-                if result.len() >= 1073741824 {
-                    println!("Error: Buffer over 1GB! Abort!");
-                    return result;
-                }
+            // Move to the next byte on bit pointer overflow
+            if self.sampler.current_bit > 7 {
+                self.sampler.current_byte += 1;
+                self.sampler.current_bit = 0;
+            }
+            self.sampler.sample_counter = SAMPLE_SPEED;
+        } else {
+            self.sampler.sample_counter -= 1;
+        }
+    }
+
+    /// Advance the voices by some frames without synthesizing them
+    fn skip_frames(&mut self, frames: u32) {
+        let mut remaining: u32 = frames;
+        while remaining > 0 && self.is_sampler_playing() {
+            self.step_sampler();
+            remaining -= 1;
+        }
+        if remaining > 0 {
+            // The sample is over, only the playback rate counter keeps running
+            let period: u32 = SAMPLE_SPEED as u32 + 1;
+            let counter: u32 = self.sampler.sample_counter as u32;
+            if remaining > counter {
+                self.channels[TOTAL_VOICES - 1].output = 0;
             }
+            self.sampler.sample_counter = ((counter + period - remaining % period) % period) as u8;
+        }
+
+        if frames == 0 {
+            return;
+        }
+        for channel in self.channels.iter_mut().take(TOTAL_VOICES - 1) {
+            let frequency: u64 = channel.frequency.max(1) as u64;
+            let pitch_counter: u64 = channel.pitch_counter as u64 + frames as u64 * channel.pitch_step() as u64;
+            channel.pitch_counter = (pitch_counter % frequency) as u16;
+            if channel.pitch_counter <= channel.waveform {
+                channel.output = 1;
+            }
+            if channel.pitch_counter >= channel.waveform {
+                channel.output = 0;
+            }
+        }
+    }
 
+    /// Output level of a voice, silent when muted
+    fn voice_level(&self, v: usize) -> u8 {
+        if self.channels[v].is_muted {
+            0
+        } else {
+            self.channels[v].output * AMPLITUDE
+        }
+    }
 
+    /// Synthesize one frame (one sample per voice) and advance the sequencer
+    fn step(&mut self) -> Result<(), MMMLError> {
+        self.frame_length = 0;
+        self.frame_position = 0;
 
-            /**************************
-             *  Data Processing Code  *
-             **************************/
+        /**********************
+         *  Synthesizer Code  *
+         **********************/
 
-            if self.tick_counter == 0 {
-                // Variable tempo, sets the fastest / smallest possible clock event.
-                self.tick_counter = self.tick_speed;
-                let mut has_ended: [bool; TOTAL_VOICES] = [false, false, false, false];
+        self.step_sampler();
 
+        // Calculate pulse values
+        for v in 0..TOTAL_VOICES - 1 {
+            self.channels[v].pitch_counter += self.channels[v].pitch_step();
+            if self.channels[v].pitch_counter >= self.channels[v].frequency {
+                self.channels[v].pitch_counter %= self.channels[v].frequency;
+            }
+            if self.channels[v].pitch_counter <= self.channels[v].waveform {
+                self.channels[v].output = 1;
+            }
+            if self.channels[v].pitch_counter >= self.channels[v].waveform {
+                self.channels[v].output = 0;
+            }
+        }
+
+        match self.mix_mode {
+            MixMode::Pim => {
+                // Output and interleave samples using PIM
                 for v in 0..TOTAL_VOICES {
-                    // If the note ended, start processing the next byte of data.
-                    if self.channels[v].length == 0 {
-                        'voice_processing: loop {
-                            // Temporary storage of data for quick processing.
-                            let data_ptr = self.channels[v].data_pointer as usize;
-                            // First nibble of data
-                            let buffer1 = (mmml_source[data_ptr] >> 4) & 0x0F;
-                            // Second nibble of data
-                            let buffer2 = mmml_source[data_ptr] & 0x0F;
-
-                            // Function command
-                            if buffer1 == 15 {
-                                // Another buffer for commands that require an additional byte.
-                                let buffer3 = mmml_source[data_ptr + 1];
-
-                                // Process function commands using match
-                                match buffer2 {
-                                    LOOP_START => {
-                                        self.channels[v].loops_active += 1;
-                                        let active_loop = (self.channels[v].loops_active - 1) as usize;
-                                        self.channels[v].loop_point[active_loop] = self.channels[v].data_pointer + 2;
-                                        self.channels[v].loop_duration[active_loop] = buffer3 as u16 - 1;
-                                        self.channels[v].data_pointer += 2;
-                                    },
-                                    LOOP_END => {
-                                        let active_loop = (self.channels[v].loops_active - 1) as usize;
-                                        if self.channels[v].loop_duration[active_loop] > 0 {
-                                            self.channels[v].data_pointer = self.channels[v].loop_point[active_loop];
-                                            self.channels[v].loop_duration[active_loop] -= 1;
-                                        } else {
-                                            self.channels[v].loops_active -= 1;
-                                            self.channels[v].data_pointer += 1;
-                                        }
-                                    },
-                                    MACRO => {
-                                        self.channels[v].pointer_location = self.channels[v].data_pointer + 2;
-                                        let macro_ptr = ((buffer3 as usize) + TOTAL_VOICES) * 2;
-                                        self.channels[v].data_pointer = ((mmml_source[macro_ptr] as u16) << 8) | 
-                                                                       (mmml_source[macro_ptr + 1] as u16);
-                                    },
-                                    TEMPO => {
-                                        self.tick_speed = (buffer3 as u16) << 3;
-                                        self.channels[v].data_pointer += 2;
-                                    },
-                                    4 => {
-                                        println!("Warning: Transpose command found! This command isn't supported by the player! Skipping...");
-                                        self.channels[v].data_pointer += 2;
-                                    },
-                                    5 => {
-                                        println!("Warning: Instrumeent command found! This command isn't supported by the player! Skipping...");
-                                        self.channels[v].data_pointer += 2;
-                                    }
-                                    6 => {
-                                        println!("Warning: Tie command found! This command isn't supported by the player! Skipping...");
-                                        self.channels[v].data_pointer += 1;
-                                    }
-                                    7 => {
-                                        println!("Warning: Panning command found! This command isn't supported by the player! Skipping...");
-                                        self.channels[v].data_pointer += 2;
-                                    }
-                                    // Debug pointer flag
-                                    14 => {
-                                        println!("Flag location: {}", self.channels[v].data_pointer);
-                                        self.channels[v].data_pointer += 1;
-                                    },
-                                    CHANNEL_END => {
-                                        if self.channels[v].pointer_location != 0 {
-                                            self.channels[v].data_pointer = self.channels[v].pointer_location;
-                                            self.channels[v].pointer_location = 0;
-                                        } else {
-                                            // Goes to loop again
-                                            self.channels[v].data_pointer = ((mmml_source[v * 2] as u16) << 8) | 
-                                                                           (mmml_source[v * 2 + 1] as u16);
-                                            has_ended[v] = true;
-                                        }
-                                    },
-                                    _ => {
-                                        println!("Warning: Unknown command found: {:02X}. Skipping...", mmml_source[data_ptr]);
-                                        self.channels[v].data_pointer += 1;
-                                    }
-                                }
-                                let mut all_end: bool = true;
-                                for v in 0..TOTAL_VOICES {
-                                    if !has_ended[v] {
-                                        all_end = false;
-                                        break;
-                                    }
-                                }
-                                if all_end {
-                                    return result;
-                                }
+                    let level: u8 = self.voice_level(v);
+                    if self.is_stereo {
+                        // Split the voice between the left and right outputs
+                        let (left, right) = self.channels[v].pan_gains();
+                        self.push_sample((level as u16 * left / 127) as u8 + DC_OFFSET);
+                        self.push_sample((level as u16 * right / 127) as u8 + DC_OFFSET);
+                    } else {
+                        self.push_sample(level + DC_OFFSET);
+                    }
+                }
+            },
+            MixMode::Summed => {
+                // Sum the voices, keeping the samples of each voice alone for the stems
+                let mut mix: [u32; 2] = [0; 2];
+                for v in 0..TOTAL_VOICES {
+                    let level: u32 = self.voice_level(v) as u32 * self.channels[v].gain as u32 / 127;
+                    let levels: [u32; 2] = if self.is_stereo {
+                        let (left, right) = self.channels[v].pan_gains();
+                        [level * left as u32 / 127, level * right as u32 / 127]
+                    } else {
+                        [level, 0]
+                    };
+                    for (output, level) in levels.iter().enumerate() {
+                        mix[output] += level;
+                        self.voice_frames[v][output] = self.fade(summed_sample(*level));
+                    }
+                }
+                let outputs: usize = if self.is_stereo { 2 } else { 1 };
+                for level in mix[..outputs].iter() {
+                    self.push_sample(summed_sample(*level));
+                }
+            }
+        }
+
+        self.advance_frames(1);
+        self.check_end_position();
+        if self.has_ended {
+            return Ok(());
+        }
+
+        /**************************
+         *  Data Processing Code  *
+         **************************/
+
+        if self.tick_counter == 0 {
+            // Variable tempo, sets the fastest / smallest possible clock event.
+            self.tick_counter = self.tick_speed;
+            self.process_tick()?;
+            self.check_end_position();
+        } else {
+            self.tick_counter -= 1;
+        }
+        Ok(())
+    }
+
+    /// Process one sequencer tick, reading the next events of the channels whose note is over
+    fn process_tick(&mut self) -> Result<(), MMMLError> {
+        self.tick_count += 1;
+        let mut has_ended: [bool; TOTAL_VOICES] = [false, false, false, false];
 
-                                continue 'voice_processing;
+        // Step the instruments of the pulse voices
+        for v in 0..TOTAL_VOICES - 1 {
+            self.channels[v].update_instrument();
+        }
+
+        for v in 0..TOTAL_VOICES {
+            // If the note ended, start processing the next byte of data.
+            if self.channels[v].length == 0 {
+                'voice_processing: loop {
+                    let data_ptr = self.channels[v].data_pointer as usize;
+                    let instruction = Instruction::decode(&self.mmml_source, data_ptr)
+                        .ok_or(MMMLError::malformed(data_ptr, v, "unexpected end of data"))?;
+
+                    match instruction {
+                        Instruction::LoopStart(count) => {
+                            if self.channels[v].loops_active as usize >= MAXLOOPS {
+                                return Err(MMMLError::malformed(data_ptr, v, "too many nested loops"));
                             }
-                            
-                            match buffer1 {
-                                OCTAVE => {
-                                    self.channels[v].octave = 2 << buffer2;
-                                    self.channels[v].data_pointer += 1;
-                                    continue 'voice_processing;
-                                },
-                                VOLUME => {
-                                    self.channels[v].volume = buffer2;
-                                    self.channels[v].data_pointer += 1;
-                                    continue 'voice_processing;
-                                },
-                                _ => {}
+                            if count == 0 {
+                                return Err(MMMLError::malformed(data_ptr, v, "loop repeated zero times"));
                             }
-
-                            // Note value processing
-                            if buffer1 != 0 && buffer1 < 14 {
-                                if v < TOTAL_VOICES - 1 {
-                                    let buffer4 = NOTES[buffer1 as usize];
-                                    self.channels[v].frequency = buffer4;
-
-                                    /* Calculate the waveform duty cycle by dividing the frequency by
-                                     * powers of two. */
-                                    self.channels[v].waveform = buffer4 >> self.channels[v].volume;
-                                } else {
-                                    // Reset the sampler
-                                    self.sampler.current_bit = 0;
-                                    self.sampler.current_byte = SAMPLE_INDICIES[(buffer1 - 1) as usize];
-                                    self.sampler.current_sample = SAMPLE_INDICIES[buffer1 as usize];
-                                }
+                            self.channels[v].loops_active += 1;
+                            let active_loop = (self.channels[v].loops_active - 1) as usize;
+                            self.channels[v].loop_point[active_loop] = self.channels[v].data_pointer + 2;
+                            self.channels[v].loop_duration[active_loop] = count as u16 - 1;
+                            self.channels[v].data_pointer += 2;
+                            self.record(SequencerEvent::LoopStart { channel: v, count, depth: self.channels[v].loops_active });
+                        },
+                        Instruction::LoopEnd => {
+                            if self.channels[v].loops_active == 0 {
+                                return Err(MMMLError::malformed(data_ptr, v, "loop end without loop start"));
+                            }
+                            let active_loop = (self.channels[v].loops_active - 1) as usize;
+                            if self.channels[v].loop_duration[active_loop] > 0 {
+                                self.channels[v].data_pointer = self.channels[v].loop_point[active_loop];
+                                self.channels[v].loop_duration[active_loop] -= 1;
                             } else {
-                                // Rest
-                                self.channels[v].waveform = 0;
+                                self.channels[v].loops_active -= 1;
+                                self.channels[v].data_pointer += 1;
                             }
-
-                            // Note duration value
-                            self.channels[v].length = match buffer2 {
-                                0..=7 => 0x7F >> buffer2,         // Standard duration
-                                _ => 95 >> (buffer2 & 7),        // Dotted (1 + 1/2) duration
-                            };
+                        },
+                        Instruction::Macro(index) => {
+                            if index as usize >= macro_count(self.header_size) {
+                                return Err(MMMLError::malformed(data_ptr, v, "macro index past the header table"));
+                            }
+                            let return_pointer: u16 = self.channels[v].data_pointer + 2;
+                            let depth: usize = self.channels[v].call_depth as usize;
+                            if self.is_single_macro_level {
+                                // Original player: a single return address, overwritten by nested calls
+                                self.channels[v].call_stack[0] = return_pointer;
+                                self.channels[v].call_depth = 1;
+                            } else if depth >= MAX_MACRO_DEPTH {
+                                return Err(MMMLError::malformed(data_ptr, v, "too many nested macro calls"));
+                            } else {
+                                self.channels[v].call_stack[depth] = return_pointer;
+                                self.channels[v].call_depth += 1;
+                            }
+                            self.channels[v].data_pointer = self.read_pointer(macro_pointer_offset(index), v)?;
+                            self.record(SequencerEvent::Macro { channel: v, index });
+                        },
+                        Instruction::Tempo(tempo) => {
+                            self.tick_speed = (tempo as u16) << 3;
+                            self.record(SequencerEvent::Tempo { channel: v, tempo });
+                            self.channels[v].data_pointer += 2;
+                        },
+                        Instruction::Transpose(transpose) => {
+                            // Signed semitone offset applied to every following note
+                            self.channels[v].transpose = transpose;
+                            self.channels[v].data_pointer += 2;
+                        },
+                        Instruction::Instrument(instrument) => {
+                            if (instrument as usize) < INSTRUMENTS.len() {
+                                self.channels[v].instrument = instrument;
+                            } else {
+                                eprintln!("Warning: Unknown instrument {}. Using the default one...", instrument);
+                                self.channels[v].instrument = 0;
+                            }
+                            self.channels[v].data_pointer += 2;
+                        },
+                        Instruction::Tie => {
+                            // The next note sustains the current one instead of retriggering it
+                            self.channels[v].tie_pending = true;
+                            self.channels[v].data_pointer += 1;
+                        },
+                        Instruction::Panning(pan) => {
+                            // Signed pan position: negative is left, positive is right
+                            self.channels[v].pan = pan;
+                            self.channels[v].data_pointer += 2;
+                        },
+                        // Debug pointer flag
+                        Instruction::DebugFlag => {
+                            eprintln!("Flag location: {}", self.channels[v].data_pointer);
+                            self.channels[v].data_pointer += 1;
+                        },
+                        Instruction::ChannelEnd => {
+                            if self.channels[v].call_depth > 0 {
+                                // Return from the macro
+                                self.channels[v].call_depth -= 1;
+                                self.channels[v].data_pointer = self.channels[v].call_stack[self.channels[v].call_depth as usize];
+                            } else {
+                                if has_ended[v] {
+                                    return Err(MMMLError::malformed(data_ptr, v, "channel without any note or rest"));
+                                }
+                                // Goes to loop again
+                                self.channels[v].data_pointer = self.read_pointer(channel_pointer_offset(v), v)?;
+                                has_ended[v] = true;
+                                self.passes[v] += 1;
+                                self.record(SequencerEvent::ChannelEnd { channel: v });
+                                let is_song_end: bool = match self.end_condition {
+                                    EndCondition::AllChannels => has_ended.iter().all(|ended| *ended),
+                                    EndCondition::Loops(count) => self.passes.iter().all(|passes| *passes >= count),
+                                    EndCondition::FirstChannel => true,
+                                    EndCondition::Position(_) | EndCondition::Duration(_) => false
+                                };
+                                if is_song_end && self.finish() {
+                                    return Ok(());
+                                }
+                            }
+                        },
+                        Instruction::Unknown(command) => {
+                            eprintln!("Warning: Unknown command found: {:02X}. Skipping...", command);
+                            self.channels[v].data_pointer += 1;
+                        },
+                        Instruction::Octave(octave) => {
+                            if octave > 6 {
+                                return Err(MMMLError::malformed(data_ptr, v, "octave out of range"));
+                            }
+                            self.channels[v].octave = 2 << octave;
+                            self.channels[v].data_pointer += 1;
+                        },
+                        Instruction::Volume(volume) => {
+                            self.channels[v].volume = volume;
+                            self.channels[v].data_pointer += 1;
+                        },
+                        Instruction::Note { note, duration } => {
+                            self.play_note(v, note, duration, data_ptr)?;
 
                             // Next element in data
                             self.channels[v].data_pointer += 1;
                             break 'voice_processing;
                         }
-                    } else {
-                        // Keep waiting until the note is over...
-                        self.channels[v].length -= 1;
                     }
                 }
             } else {
-                self.tick_counter -= 1;
+                // Keep waiting until the note is over...
+                self.channels[v].length -= 1;
             }
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for MMMLSynthesizer {
+    type Item = u8;
 
+    fn next(&mut self) -> Option<u8> {
+        if self.frame_position >= self.frame_length {
+            if self.has_ended {
+                return None;
+            }
+            if let Err(err) = self.step() {
+                self.error = Some(err);
+                self.has_ended = true;
+                return None;
+            }
         }
+        let sample: u8 = self.frame[self.frame_position];
+        self.frame_position += 1;
+        Some(sample)
     }
 }
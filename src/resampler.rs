@@ -0,0 +1,118 @@
+/// Band-limited resampler
+///
+/// Windowed sinc interpolation between two sample rates, low-pass filtering
+/// below the Nyquist frequency of the lowest rate so the 1-bit PIM stream
+/// doesn't alias when brought down to usual playback rates.
+use std::f64::consts::PI;
+
+const ZERO_CROSSINGS: f64 = 16.0; // sinc lobes on each side of the kernel
+const ROLLOFF: f64 = 0.9;         // cutoff position relative to the Nyquist frequency
+const TABLE_RESOLUTION: usize = 512; // kernel table entries per input sample
+
+/// Streaming resampler for one channel
+pub struct Resampler {
+    input_rate: u32,
+    output_rate: u32,
+    /// Kernel half length in input samples
+    half_length: usize,
+    /// Kernel values from the center to its end, `TABLE_RESOLUTION` per input sample
+    table: Vec<f32>,
+    /// Input samples still needed by the kernel
+    history: Vec<f32>,
+    /// Index of the first history sample in the input stream
+    history_start: u64,
+    /// Number of samples pushed so far
+    input_count: u64,
+    /// Index of the next output sample
+    output_count: u64
+}
+
+impl Resampler {
+    pub fn new(input_rate: u32, output_rate: u32) -> Self {
+        let ratio: f64 = (output_rate as f64 / input_rate as f64).min(1.0);
+        let cutoff: f64 = 0.5 * ratio * ROLLOFF;
+        let half_length: usize = (ZERO_CROSSINGS / (2.0 * cutoff)).ceil() as usize;
+        // The kernel is symmetric, one side is enough (plus an entry past its end to interpolate)
+        let table: Vec<f32> = (0..=half_length * TABLE_RESOLUTION + 1)
+            .map(|index| kernel(index as f64 / TABLE_RESOLUTION as f64, cutoff, half_length) as f32)
+            .collect();
+        Resampler {
+            input_rate,
+            output_rate,
+            half_length,
+            table,
+            history: Vec::new(),
+            history_start: 0,
+            input_count: 0,
+            output_count: 0
+        }
+    }
+
+    /// Position of the next output sample in the input stream
+    fn next_time(&self) -> f64 {
+        self.output_count as f64 * self.input_rate as f64 / self.output_rate as f64
+    }
+
+    /// Push an input sample, appending the output samples it completes
+    pub fn push(&mut self, sample: f32, output: &mut Vec<f32>) {
+        self.history.push(sample);
+        self.input_count += 1;
+        self.drain(output);
+    }
+
+    /// Flush the samples still waiting for future input, padding with silence
+    pub fn flush(&mut self, output: &mut Vec<f32>) {
+        let end: f64 = self.input_count as f64;
+        while self.next_time() < end {
+            self.history.push(0.0);
+            self.input_count += 1;
+            self.drain(output);
+        }
+    }
+
+    fn drain(&mut self, output: &mut Vec<f32>) {
+        while self.next_time() + (self.half_length as f64) < self.input_count as f64 {
+            output.push(self.interpolate(self.next_time()));
+            self.output_count += 1;
+        }
+        // Forget the samples the kernel won't reach anymore
+        let needed: u64 = (self.next_time() as u64).saturating_sub(self.half_length as u64);
+        if needed > self.history_start + 4096 {
+            self.history.drain(..(needed - self.history_start) as usize);
+            self.history_start = needed;
+        }
+    }
+
+    fn interpolate(&self, time: f64) -> f32 {
+        let center: usize = (time.floor() as u64 - self.history_start) as usize;
+        let offset: f64 = time - time.floor();
+        // Samples at and before the output position, then the ones after it
+        self.weighted_sum(self.history[..=center].iter().rev(), offset)
+            + self.weighted_sum(self.history[center + 1..].iter(), 1.0 - offset)
+    }
+
+    /// Sum of samples moving away from the output position, the first one at `distance`.
+    ///
+    /// Successive samples are one input sample apart, so their kernel values
+    /// are `TABLE_RESOLUTION` entries apart with the same interpolation fraction.
+    fn weighted_sum<'a>(&self, samples: impl Iterator<Item = &'a f32>, distance: f64) -> f32 {
+        let position: f64 = distance * TABLE_RESOLUTION as f64;
+        let start: usize = position as usize;
+        let fraction: f32 = (position - start as f64) as f32;
+        let weights = self.table[start..].iter().zip(&self.table[start + 1..]).step_by(TABLE_RESOLUTION);
+        samples.zip(weights).map(|(sample, (low, high))| sample * (low + (high - low) * fraction)).sum()
+    }
+}
+
+/// Blackman windowed sinc low-pass kernel
+fn kernel(distance: f64, cutoff: f64, half_length: usize) -> f64 {
+    let window_position: f64 = distance / half_length as f64;
+    if window_position.abs() >= 1.0 {
+        return 0.0;
+    }
+    let x: f64 = 2.0 * cutoff * distance;
+    let sinc: f64 = if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+    let phase: f64 = PI * (window_position + 1.0);
+    let window: f64 = 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+    2.0 * cutoff * sinc * window
+}
@@ -0,0 +1,177 @@
+/// Percussion sample banks
+///
+/// The sampler channel plays 1-bit samples packed into a bitstream (least
+/// significant bit first), located by a table of byte offsets holding the
+/// start of every sample followed by the end of the last one.
+///
+/// A bank file stores the number of samples (one byte), the offset table
+/// as big-endian 16-bit values and the bitstream.
+use std::path::Path;
+
+use crate::error::MMMLError;
+
+// Location of individual samples in sample array
+const SAMPLE_INDICIES: [u8; 6] = [0, 19, 34, 74, 118, 126];
+
+// Raw PWM sample data
+const SAMPLES: [u8; SAMPLE_LENGTH] = [
+    // bwoop (0)
+    0b10101010, 0b10110110, 0b10000111, 0b11111000,
+    0b10000100, 0b00110111, 0b11101000, 0b11000001,
+    0b00000111, 0b00111101, 0b11111000, 0b11100000,
+    0b10010001, 0b10000111, 0b00000111, 0b00001111,
+    0b00001111, 0b00011011, 0b00011110,
+    // beep (19)
+    0b10101010, 0b00101010, 0b00110011, 0b00110011,
+    0b00110011, 0b00110011, 0b00110011, 0b11001101,
+    0b11001100, 0b11001100, 0b11001100, 0b10101100,
+    0b10011001, 0b00110001, 0b00110011,
+    // kick (34)
+    0b10010101, 0b10110010, 0b00000000, 0b11100011,
+    0b11110000, 0b00000000, 0b11111111, 0b00000000,
+    0b11111110, 0b00000000, 0b00000000, 0b00000000,
+    0b11111111, 0b11111111, 0b11111111, 0b00100101,
+    0b00000000, 0b00000000, 0b00000000, 0b00000000,
+    0b11111111, 0b11110111, 0b11111111, 0b11111111,
+    0b11111111, 0b10111111, 0b00010010, 0b00000000,
+    0b10000000, 0b00000000, 0b00000000, 0b00000000,
+    0b00000000, 0b11101110, 0b11111111, 0b11111111,
+    0b11111111, 0b11110111, 0b11111111, 0b11111110,
+    // snare (74)
+    0b10011010, 0b10011010, 0b10101010, 0b10010110,
+    0b01110100, 0b10010101, 0b10001010, 0b11011110,
+    0b01110100, 0b10100000, 0b11110111, 0b00100101,
+    0b01110100, 0b01101000, 0b11111111, 0b01011011,
+    0b01000001, 0b10000000, 0b11010100, 0b11111101,
+    0b11011110, 0b00010010, 0b00000100, 0b00100100,
+    0b11101101, 0b11111011, 0b01011011, 0b00100101,
+    0b00000100, 0b10010001, 0b01101010, 0b11011111,
+    0b01110111, 0b00010101, 0b00000010, 0b00100010,
+    0b11010101, 0b01111010, 0b11101111, 0b10110110,
+    0b00100100, 0b10000100, 0b10100100, 0b11011010,
+    // hi-hat (118)
+    0b10011010, 0b01110100, 0b11010100, 0b00110011,
+    0b00110011, 0b11101000, 0b11101000, 0b01010101,
+    0b01010101,
+    // end (126)
+];
+
+const SAMPLE_LENGTH: usize = 127; // the length of the sample array
+pub const BUILTIN_SAMPLES: usize = SAMPLE_INDICIES.len() - 1; // the number of built-in percussion samples
+pub const MAX_SAMPLES: usize = 12; // one sample per note of the octave
+
+/// Percussion samples played by the sampler channel, notes 1 to 12 pick them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SampleBank {
+    /// Start of every sample in the bitstream, then the end of the last one
+    indices: Vec<u16>,
+    data: Vec<u8>
+}
+
+impl Default for SampleBank {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl SampleBank {
+    /// The bank of the original player: bwoop, beep, kick, snare and hi-hat
+    pub fn builtin() -> Self {
+        SampleBank {
+            indices: SAMPLE_INDICIES.iter().map(|index| *index as u16).collect(),
+            data: SAMPLES.to_vec()
+        }
+    }
+
+    /// Read a bank from the content of a bank file
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MMMLError> {
+        let count: usize = match bytes.first() {
+            Some(count) => *count as usize,
+            None => return Err(MMMLError::InvalidSampleBank("empty file"))
+        };
+        if count == 0 || count > MAX_SAMPLES {
+            return Err(MMMLError::InvalidSampleBank("the bank must hold 1 to 12 samples"));
+        }
+        let table_end: usize = 1 + (count + 1) * 2;
+        if bytes.len() < table_end {
+            return Err(MMMLError::InvalidSampleBank("truncated index table"));
+        }
+        let indices: Vec<u16> = bytes[1..table_end]
+            .chunks_exact(2)
+            .map(|pointer| u16::from_be_bytes([pointer[0], pointer[1]]))
+            .collect();
+        let bank: SampleBank = SampleBank { indices, data: bytes[table_end..].to_vec() };
+        bank.check()?;
+        Ok(bank)
+    }
+
+    /// Read a bank file
+    pub fn load(path: &Path) -> Result<Self, MMMLError> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
+    /// Content of the bank file of this bank
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = vec![self.sample_count() as u8];
+        for index in &self.indices {
+            bytes.extend_from_slice(&index.to_be_bytes());
+        }
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    /// Append a sample after the last one, returning its number (the note playing it)
+    pub fn push(&mut self, sample: &[u8]) -> Result<u8, MMMLError> {
+        if self.sample_count() >= MAX_SAMPLES {
+            return Err(MMMLError::InvalidSampleBank("the bank already holds 12 samples"));
+        }
+        if sample.is_empty() {
+            return Err(MMMLError::InvalidSampleBank("empty sample"));
+        }
+        let end: usize = self.indices.last().copied().unwrap_or(0) as usize + sample.len();
+        if end > u16::MAX as usize {
+            return Err(MMMLError::InvalidSampleBank("bitstream too long"));
+        }
+        // Bytes past the end of the last sample are never played
+        self.data.truncate(end - sample.len());
+        self.data.extend_from_slice(sample);
+        self.indices.push(end as u16);
+        Ok(self.sample_count() as u8)
+    }
+
+    fn check(&self) -> Result<(), MMMLError> {
+        if self.data.len() > u16::MAX as usize {
+            return Err(MMMLError::InvalidSampleBank("bitstream too long"));
+        }
+        if self.indices.windows(2).any(|pair| pair[0] > pair[1]) {
+            return Err(MMMLError::InvalidSampleBank("sample offsets out of order"));
+        }
+        // The sampler stops one byte before the end, an empty sample would never stop
+        if self.indices.windows(2).any(|pair| pair[0] == pair[1]) {
+            return Err(MMMLError::InvalidSampleBank("empty sample"));
+        }
+        if self.indices.last().is_some_and(|end| *end as usize > self.data.len()) {
+            return Err(MMMLError::InvalidSampleBank("sample offset past the bitstream"));
+        }
+        Ok(())
+    }
+
+    pub fn sample_count(&self) -> usize {
+        self.indices.len() - 1
+    }
+
+    /// Start and end offsets of a sample, numbered from 1 like the notes
+    pub(crate) fn range(&self, sample: u8) -> Option<(u16, u16)> {
+        let sample: usize = (sample as usize).checked_sub(1)?;
+        Some((*self.indices.get(sample)?, *self.indices.get(sample + 1)?))
+    }
+
+    /// Length of the bitstream in bytes
+    pub(crate) fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub(crate) fn byte(&self, offset: u16) -> u8 {
+        self.data[offset as usize]
+    }
+}
@@ -0,0 +1,125 @@
+/// 1-bit sample conversion
+///
+/// Turns audio into the packed bitstream played by the sampler channel:
+/// resampled to the rate the sampler plays its bits at, then reduced to
+/// one bit per sample by thresholding, delta-sigma modulation or pulse
+/// width modulation.
+use std::{io::{Error, ErrorKind}, path::Path, str::FromStr};
+
+use hound::{SampleFormat, WavReader};
+
+use crate::{
+    error::MMMLError,
+    mmml_engine::SAMPLER_RATE,
+    resampler::Resampler,
+    sink::{AudioSink, StreamSpec}
+};
+
+const PWM_PERIOD: usize = 8;  // bits per pulse, one byte like the built-in samples
+const MAX_LEVEL: f32 = 127.0; // waveform high position
+
+/// How the audio is reduced to one bit per sample
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BitConversion {
+    /// High when the signal is above zero, keeps the loudest transients only
+    Threshold,
+    /// First order delta-sigma modulation, the density of high bits follows the signal
+    #[default]
+    DeltaSigma,
+    /// One pulse per byte, its width follows the signal
+    Pwm
+}
+
+impl FromStr for BitConversion {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "threshold" => Ok(BitConversion::Threshold),
+            "delta-sigma" => Ok(BitConversion::DeltaSigma),
+            "pwm" => Ok(BitConversion::Pwm),
+            _ => Err(format!("unknown conversion '{}', expected threshold, delta-sigma or pwm", name))
+        }
+    }
+}
+
+fn invalid_wav(err: hound::Error) -> MMMLError {
+    MMMLError::Io(Error::new(ErrorKind::InvalidData, err))
+}
+
+/// Read a WAV file mixed down to mono, returning its samples (-1.0 - 1.0) and sample rate
+pub fn read_wav(path: &Path) -> Result<(Vec<f32>, u32), MMMLError> {
+    let reader = WavReader::open(path).map_err(invalid_wav)?;
+    let spec = reader.spec();
+    let samples: Vec<f32> = match spec.sample_format {
+        SampleFormat::Float => reader.into_samples::<f32>().collect::<Result<_, _>>(),
+        SampleFormat::Int => {
+            let scale: f32 = (1u32 << (spec.bits_per_sample - 1)) as f32;
+            reader.into_samples::<i32>().map(|sample| sample.map(|sample| sample as f32 / scale)).collect()
+        }
+    }.map_err(invalid_wav)?;
+    let mono: Vec<f32> = samples
+        .chunks(spec.channels.max(1) as usize)
+        .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+        .collect();
+    Ok((mono, spec.sample_rate))
+}
+
+/// Convert audio into a sample for a `SampleBank`, normalized to its peak.
+///
+/// Fails on empty or silent audio, which would give a sample the sampler never plays.
+pub fn convert_sample(samples: &[f32], sample_rate: u32, conversion: BitConversion) -> Result<Vec<u8>, MMMLError> {
+    let mut resampled: Vec<f32> = Vec::with_capacity(samples.len());
+    if sample_rate == SAMPLER_RATE {
+        resampled.extend_from_slice(samples);
+    } else {
+        let mut resampler: Resampler = Resampler::new(sample_rate, SAMPLER_RATE);
+        for sample in samples {
+            resampler.push(*sample, &mut resampled);
+        }
+        resampler.flush(&mut resampled);
+    }
+    let peak: f32 = resampled.iter().fold(0.0, |peak, sample| peak.max(sample.abs()));
+    if peak == 0.0 {
+        return Err(MMMLError::InvalidSampleBank("empty or silent sample"));
+    }
+    let scale: f32 = 1.0 / peak;
+    // Levels from 0.0 (low) to 1.0 (high)
+    let levels: Vec<f32> = resampled.iter().map(|sample| (sample * scale + 1.0) / 2.0).collect();
+
+    let bits: Vec<bool> = match conversion {
+        BitConversion::Threshold => levels.iter().map(|level| *level > 0.5).collect(),
+        BitConversion::DeltaSigma => {
+            let mut error: f32 = 0.0;
+            levels.iter().map(|level| {
+                let target: f32 = level + error;
+                let bit: bool = target >= 0.5;
+                error = target - if bit { 1.0 } else { 0.0 };
+                bit
+            }).collect()
+        },
+        BitConversion::Pwm => levels.chunks(PWM_PERIOD).flat_map(|pulse| {
+            let width: usize = (pulse.iter().sum::<f32>() / pulse.len() as f32 * PWM_PERIOD as f32).round() as usize;
+            (0..PWM_PERIOD).map(move |bit| bit < width)
+        }).collect()
+    };
+
+    // Packed from the least significant bit, as the sampler reads them
+    let mut sample: Vec<u8> = bits.chunks(8)
+        .map(|byte| byte.iter().enumerate().fold(0, |packed, (bit, high)| packed | ((*high as u8) << bit)))
+        .collect();
+    // The sampler stops one byte before the end of a sample
+    sample.push(0);
+    Ok(sample)
+}
+
+/// Play a sample into an audio sink as the sampler channel would, at `SAMPLER_RATE`
+pub fn preview_sample(sample: &[u8], sink: &mut dyn AudioSink) -> Result<(), MMMLError> {
+    let played: &[u8] = &sample[..sample.len().saturating_sub(1)];
+    let levels: Vec<f32> = played.iter()
+        .flat_map(|byte| (0..8).map(move |bit| if (byte >> bit) & 1 == 1 { MAX_LEVEL } else { 0.0 }))
+        .collect();
+    sink.open(StreamSpec { channels: 1, sample_rate: SAMPLER_RATE })?;
+    sink.write(&levels)?;
+    sink.close()
+}
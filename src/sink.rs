@@ -0,0 +1,254 @@
+/// Audio sinks
+///
+/// The render loop streams the synthesizer output into an `AudioSink`,
+/// resampled to the requested rate: a WAV file, raw PCM samples,
+/// nothing at all or (with the `playback` feature) the audio device.
+use std::io::{stdout, BufWriter, Stdout, Write};
+
+use crate::{
+    error::MMMLError,
+    mmml_engine::MMMLSynthesizer,
+    resampler::Resampler,
+    wav::WavFormat
+};
+
+const MAX_LEVEL: f32 = 127.0;    // waveform high position
+const DC_CUTOFF: f32 = 5.0;      // DC blocker cutoff frequency in Hz
+const BATCH_SIZE: usize = 4096;  // samples sent to the sink at once
+
+/// Layout of the samples written into a sink
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamSpec {
+    /// Number of interleaved channels (1 or 2)
+    pub channels: u16,
+    pub sample_rate: u32
+}
+
+/// Destination of the rendered samples
+pub trait AudioSink {
+    /// Prepare the sink, called once before any sample is written
+    fn open(&mut self, spec: StreamSpec) -> Result<(), MMMLError>;
+    /// Write interleaved waveform levels (0 - 127, fractional once resampled)
+    fn write(&mut self, levels: &[f32]) -> Result<(), MMMLError>;
+    /// Flush the sink once the song is over
+    fn close(&mut self) -> Result<(), MMMLError>;
+}
+
+/// One-pole high-pass filter removing the DC level of the unipolar waveform
+#[derive(Clone, Copy, Default)]
+struct DcBlocker {
+    previous_input: f32,
+    previous_output: f32
+}
+
+impl DcBlocker {
+    fn process(&mut self, input: f32, pole: f32) -> f32 {
+        self.previous_output = input - self.previous_input + pole * self.previous_output;
+        self.previous_input = input;
+        self.previous_output
+    }
+}
+
+/// Sample in an output format
+pub(crate) enum EncodedSample {
+    I8(i8),
+    I16(i16),
+    I24(i32),
+    F32(f32)
+}
+
+/// Converts interleaved waveform levels into an output format
+pub(crate) struct SampleEncoder {
+    format: WavFormat,
+    /// One DC blocker per channel, for the centered formats
+    dc_blockers: Vec<DcBlocker>,
+    dc_pole: f32,
+    /// Channel of the next level
+    channel: usize
+}
+
+impl SampleEncoder {
+    pub(crate) fn new(format: WavFormat, spec: StreamSpec) -> Self {
+        SampleEncoder {
+            format,
+            dc_blockers: vec![DcBlocker::default(); spec.channels as usize],
+            dc_pole: 1.0 - 2.0 * std::f32::consts::PI * DC_CUTOFF / spec.sample_rate as f32,
+            channel: 0
+        }
+    }
+
+    /// Encode the next interleaved waveform level
+    pub(crate) fn encode(&mut self, level: f32) -> EncodedSample {
+        let channel: usize = self.channel;
+        self.channel = (self.channel + 1) % self.dc_blockers.len();
+        let centered: f32 = self.dc_blockers[channel].process(level / MAX_LEVEL, self.dc_pole).clamp(-1.0, 1.0);
+        match self.format {
+            WavFormat::I8 => EncodedSample::I8(level.round().clamp(-128.0, 127.0) as i8),
+            WavFormat::I16 => EncodedSample::I16((centered * i16::MAX as f32).round() as i16),
+            WavFormat::I24 => EncodedSample::I24((centered * 8388607.0).round() as i32),
+            WavFormat::F32 => EncodedSample::F32(centered)
+        }
+    }
+}
+
+/// Sink dropping every sample, counting them
+#[derive(Debug, Default)]
+pub struct NullSink {
+    pub spec: Option<StreamSpec>,
+    pub samples: u64
+}
+
+impl AudioSink for NullSink {
+    fn open(&mut self, spec: StreamSpec) -> Result<(), MMMLError> {
+        self.spec = Some(spec);
+        Ok(())
+    }
+
+    fn write(&mut self, levels: &[f32]) -> Result<(), MMMLError> {
+        self.samples += levels.len() as u64;
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), MMMLError> {
+        Ok(())
+    }
+}
+
+/// Sink writing headerless little-endian PCM samples
+pub struct RawSink<W: Write> {
+    output: W,
+    format: WavFormat,
+    encoder: Option<SampleEncoder>
+}
+
+impl<W: Write> RawSink<W> {
+    pub fn new(output: W, format: WavFormat) -> Self {
+        RawSink { output, format, encoder: None }
+    }
+}
+
+impl RawSink<BufWriter<Stdout>> {
+    /// Raw sink writing into the standard output
+    pub fn stdout(format: WavFormat) -> Self {
+        RawSink::new(BufWriter::new(stdout()), format)
+    }
+}
+
+impl<W: Write> AudioSink for RawSink<W> {
+    fn open(&mut self, spec: StreamSpec) -> Result<(), MMMLError> {
+        self.encoder = Some(SampleEncoder::new(self.format, spec));
+        Ok(())
+    }
+
+    fn write(&mut self, levels: &[f32]) -> Result<(), MMMLError> {
+        let encoder: &mut SampleEncoder = self.encoder.as_mut().expect("raw sink written before being opened");
+        for level in levels {
+            match encoder.encode(*level) {
+                EncodedSample::I8(sample) => self.output.write_all(&sample.to_le_bytes())?,
+                EncodedSample::I16(sample) => self.output.write_all(&sample.to_le_bytes())?,
+                EncodedSample::I24(sample) => self.output.write_all(&sample.to_le_bytes()[..3])?,
+                EncodedSample::F32(sample) => self.output.write_all(&sample.to_le_bytes())?
+            }
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), MMMLError> {
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+/// Streams synthesizer samples into a sink, resampling them if needed
+pub(crate) struct SinkWriter<'a> {
+    sink: &'a mut dyn AudioSink,
+    /// One resampler per channel, empty at the native rate
+    resamplers: Vec<Resampler>,
+    /// Resampled samples waiting to be interleaved, per channel
+    pending: Vec<Vec<f32>>,
+    /// Interleaved levels waiting to be sent to the sink
+    batch: Vec<f32>,
+    /// Channel of the next synthesizer sample
+    channel: usize
+}
+
+impl<'a> SinkWriter<'a> {
+    /// Open a sink for the output of the synthesizer, at its native rate or at `sample_rate`
+    pub(crate) fn open(sink: &'a mut dyn AudioSink, mmml: &MMMLSynthesizer, sample_rate: Option<u32>) -> Result<Self, MMMLError> {
+        let channels: u16 = if mmml.is_stereo { 2 } else { 1 };
+        let native_rate: u32 = mmml.sample_rate();
+        let sample_rate: u32 = sample_rate.unwrap_or(native_rate);
+        let mut resamplers: Vec<Resampler> = Vec::new();
+        if sample_rate != native_rate {
+            for _ in 0..channels {
+                resamplers.push(Resampler::new(native_rate, sample_rate));
+            }
+        }
+        sink.open(StreamSpec { channels, sample_rate })?;
+        Ok(SinkWriter {
+            sink,
+            pending: vec![Vec::new(); resamplers.len()],
+            resamplers,
+            batch: Vec::with_capacity(BATCH_SIZE),
+            channel: 0
+        })
+    }
+
+    pub(crate) fn write(&mut self, sample: u8) -> Result<(), MMMLError> {
+        if self.resamplers.is_empty() {
+            self.batch.push(sample as f32);
+        } else {
+            let channel: usize = self.channel;
+            self.channel = (self.channel + 1) % self.resamplers.len();
+            self.resamplers[channel].push(sample as f32, &mut self.pending[channel]);
+            if self.channel == 0 {
+                self.interleave_pending();
+            }
+        }
+        if self.batch.len() >= BATCH_SIZE {
+            self.sink.write(&self.batch)?;
+            self.batch.clear();
+        }
+        Ok(())
+    }
+
+    /// Interleave the resampled samples available on every channel
+    fn interleave_pending(&mut self) {
+        let count: usize = self.pending.iter().map(Vec::len).min().unwrap_or(0);
+        for index in 0..count {
+            for channel in &self.pending {
+                self.batch.push(channel[index]);
+            }
+        }
+        for channel in &mut self.pending {
+            channel.drain(..count);
+        }
+    }
+
+    pub(crate) fn close(mut self) -> Result<(), MMMLError> {
+        for (resampler, pending) in self.resamplers.iter_mut().zip(self.pending.iter_mut()) {
+            resampler.flush(pending);
+        }
+        self.interleave_pending();
+        self.sink.write(&self.batch)?;
+        self.sink.close()
+    }
+}
+
+/// Synthesize µMML bytecode into an audio sink, at the native rate or at `sample_rate`.
+///
+/// The synthesizer configuration (muted channels, stereo) is kept as is.
+pub fn render(mmml: &mut MMMLSynthesizer, mmml_data: &[u8], sink: &mut dyn AudioSink, sample_rate: Option<u32>) -> Result<(), MMMLError> {
+    mmml.load(mmml_data)?;
+    let mut writer: SinkWriter = SinkWriter::open(sink, mmml, sample_rate)?;
+    let mut buffer: [u8; 4096] = [0; 4096];
+    loop {
+        let count: usize = mmml.fill(&mut buffer)?;
+        for sample in &buffer[..count] {
+            writer.write(*sample)?;
+        }
+        if count < buffer.len() {
+            return writer.close();
+        }
+    }
+}
@@ -0,0 +1,118 @@
+/// Note tables
+///
+/// `NOTES` holds the timer periods of the original player, tuned for the
+/// AVR clock it ran on. A `Tuning` recomputes the table for a reference
+/// pitch and a temperament, note `a` of octave 3 playing the reference.
+/// That note plays about 577.8 Hz in the original table, so the reference
+/// defaults to this pitch to keep the song in its key.
+use std::{path::Path, str::FromStr};
+
+use crate::{error::MMMLError, mmml_engine::{FRAME_RATE, NOTES}};
+
+const REFERENCE_STEP: f64 = 16.0;         // pitch step of octave 3
+const A: usize = 9;                       // position of a in the octave
+const MIN_PERIOD: f64 = 16.0;             // range kept by the pitch sweeps
+const MAX_PERIOD: f64 = 32767.0;
+
+/// Pitches of the notes c to b of an octave, in cents above c
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Temperament {
+    #[default]
+    Equal,
+    /// 5-limit just intonation from c
+    Just,
+    /// Quarter-comma meantone, from e flat to g sharp
+    Meantone,
+    Custom([f64; 12])
+}
+
+impl FromStr for Temperament {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "equal" => Ok(Temperament::Equal),
+            "just" => Ok(Temperament::Just),
+            "meantone" => Ok(Temperament::Meantone),
+            _ => Err(format!("unknown temperament '{}', expected equal, just or meantone", name))
+        }
+    }
+}
+
+impl Temperament {
+    /// Read a custom temperament: the 12 pitches of c to b in cents above c,
+    /// separated by spaces, commas or new lines, `#` starting a comment
+    pub fn from_cents(text: &str) -> Result<Self, MMMLError> {
+        let mut cents: Vec<f64> = Vec::new();
+        for line in text.lines() {
+            let line: &str = line.split('#').next().unwrap_or("");
+            for value in line.split(|c: char| c == ',' || c.is_whitespace()).filter(|value| !value.is_empty()) {
+                match value.parse::<f64>() {
+                    Ok(value) if value.is_finite() => cents.push(value),
+                    _ => return Err(MMMLError::InvalidTuning("invalid cents value"))
+                }
+            }
+        }
+        let cents: [f64; 12] = cents.try_into().map_err(|_| MMMLError::InvalidTuning("expected 12 cents values"))?;
+        Ok(Temperament::Custom(cents))
+    }
+
+    /// Read a custom temperament file, see `from_cents`
+    pub fn load(path: &Path) -> Result<Self, MMMLError> {
+        Self::from_cents(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn cents(&self) -> [f64; 12] {
+        match self {
+            Temperament::Equal => std::array::from_fn(|note| note as f64 * 100.0),
+            Temperament::Just => {
+                let ratios: [f64; 12] = [1.0, 16.0 / 15.0, 9.0 / 8.0, 6.0 / 5.0, 5.0 / 4.0, 4.0 / 3.0, 45.0 / 32.0, 3.0 / 2.0, 8.0 / 5.0, 5.0 / 3.0, 9.0 / 5.0, 15.0 / 8.0];
+                ratios.map(|ratio| 1200.0 * ratio.log2())
+            },
+            Temperament::Meantone => {
+                // Chain of fifths narrowed by a quarter of the syntonic comma
+                let fifth: f64 = 700.0 - 1200.0 * (81.0f64 / 80.0).log2() / 4.0;
+                let fifths: [i32; 12] = [0, 7, 2, -3, 4, -1, 6, 1, 8, 3, -2, 5];
+                fifths.map(|count| (count as f64 * fifth).rem_euclid(1200.0))
+            },
+            Temperament::Custom(cents) => *cents
+        }
+    }
+}
+
+/// Reference pitch and temperament of a recomputed note table
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tuning {
+    /// Pitch of note a of octave 3 in Hz (432, 440, 443...)
+    pub reference: f64,
+    pub temperament: Temperament
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Tuning { reference: Self::original_reference(FRAME_RATE as f64), temperament: Temperament::Equal }
+    }
+}
+
+impl Tuning {
+    /// Pitch of note a of octave 3 in `NOTES` for a synthesizer running at `frame_rate`
+    pub fn original_reference(frame_rate: f64) -> f64 {
+        frame_rate * REFERENCE_STEP / NOTES[A + 1] as f64
+    }
+
+    /// Timer periods of the notes, laid out as `NOTES` (the rest first)
+    pub fn note_table(&self) -> [u16; 13] {
+        self.note_table_at(FRAME_RATE as f64)
+    }
+
+    /// Timer periods of the notes for a synthesizer running at `frame_rate`, see `MMMLSynthesizer::frame_rate`
+    pub fn note_table_at(&self, frame_rate: f64) -> [u16; 13] {
+        let cents: [f64; 12] = self.temperament.cents();
+        let mut table: [u16; 13] = [NOTES[0]; 13];
+        for (note, period) in table.iter_mut().skip(1).enumerate() {
+            let frequency: f64 = self.reference * 2f64.powf((cents[note] - cents[A]) / 1200.0);
+            *period = (frame_rate * REFERENCE_STEP / frequency).round().clamp(MIN_PERIOD, MAX_PERIOD) as u16;
+        }
+        table
+    }
+}
@@ -0,0 +1,244 @@
+/// µMML bytecode validator
+///
+/// Walks the channel streams and the macros referenced by the header
+/// without playing them, reporting every problem found with its byte offset.
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use crate::{
+    bytecode::{channel_pointer_offset, macro_count, macro_pointer_offset, read_pointer, Instruction},
+    error::MMMLError,
+    mmml_engine::{MMMLSynthesizer, MAXLOOPS, MAX_MACRO_DEPTH, TOTAL_VOICES}
+};
+
+/// How serious a validation issue is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The song can't be played
+    Error,
+    /// The player skips the problem with a warning
+    Warning
+}
+
+/// A problem found in µMML bytecode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationIssue {
+    pub offset: usize,
+    /// Channel of the stream, `None` for the header and macros
+    pub channel: Option<usize>,
+    pub reason: &'static str,
+    pub severity: Severity
+}
+
+impl Display for ValidationIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self.channel {
+            Some(channel) => write!(f, "0x{:04X} (channel {}): {}", self.offset, channel + 1, self.reason),
+            None => write!(f, "0x{:04X}: {}", self.offset, self.reason)
+        }
+    }
+}
+
+impl From<ValidationIssue> for MMMLError {
+    fn from(issue: ValidationIssue) -> Self {
+        MMMLError::MalformedBytecode { offset: issue.offset, channel: issue.channel, reason: issue.reason }
+    }
+}
+
+/// Summary of a walked stream
+#[derive(Clone, Copy)]
+struct StreamInfo {
+    /// Deepest loop nesting, counting the loops of the called macros
+    max_loops: usize,
+    /// Deepest macro call nesting, counting the stream itself for macros
+    max_calls: usize,
+    /// Highest note played, counting the called macros (percussion samples on channel 4)
+    max_note: u8
+}
+
+/// Walk state of a macro, macros are walked on their first call
+#[derive(Clone, Copy)]
+enum MacroState {
+    Pending,
+    Walking,
+    /// Walked, `None` when its pointer is invalid
+    Walked(Option<StreamInfo>)
+}
+
+struct Validator<'a> {
+    mmml_source: &'a [u8],
+    header_size: usize,
+    macros: Vec<MacroState>,
+    /// Number of samples the sampler channel can play
+    sample_count: usize,
+    /// Nested macro calls jump without returning, see `MMMLSynthesizer::is_single_macro_level`
+    is_single_macro_level: bool,
+    issues: Vec<ValidationIssue>
+}
+
+/// Validate µMML bytecode, returning every problem found (the song plays when they are all warnings)
+pub fn validate(mmml_source: &[u8]) -> Vec<ValidationIssue> {
+    validate_for(&MMMLSynthesizer::new(), mmml_source)
+}
+
+/// Validate µMML bytecode played by a configured synthesizer (its sample bank and macro mode)
+pub fn validate_for(mmml: &MMMLSynthesizer, mmml_source: &[u8]) -> Vec<ValidationIssue> {
+    let mut validator: Validator = Validator {
+        mmml_source,
+        header_size: 0,
+        macros: Vec::new(),
+        sample_count: mmml.sample_bank.sample_count(),
+        is_single_macro_level: mmml.is_single_macro_level,
+        issues: Vec::new()
+    };
+    validator.validate();
+    validator.issues
+}
+
+impl Validator<'_> {
+    fn issue(&mut self, offset: usize, channel: Option<usize>, reason: &'static str) {
+        self.issues.push(ValidationIssue { offset, channel, reason, severity: Severity::Error });
+    }
+
+    fn warning(&mut self, offset: usize, channel: Option<usize>, reason: &'static str) {
+        self.issues.push(ValidationIssue { offset, channel, reason, severity: Severity::Warning });
+    }
+
+    /// Read a pointer of the header, checking it points into the data
+    fn header_pointer(&mut self, offset: usize, channel: Option<usize>) -> Option<usize> {
+        let pointer = read_pointer(self.mmml_source, offset).map(|pointer| pointer as usize);
+        match pointer {
+            Some(pointer) if pointer >= self.header_size && pointer < self.mmml_source.len() => Some(pointer),
+            Some(_) => {
+                self.issue(offset, channel, "pointer out of bounds");
+                None
+            },
+            None => {
+                self.issue(offset, channel, "truncated header");
+                None
+            }
+        }
+    }
+
+    fn validate(&mut self) {
+        let header_size = match read_pointer(self.mmml_source, channel_pointer_offset(0)) {
+            Some(header_size) => header_size as usize,
+            None => {
+                self.issue(0, None, "truncated header");
+                return;
+            }
+        };
+        if header_size < TOTAL_VOICES * 2 || header_size % 2 != 0 || header_size > self.mmml_source.len() {
+            self.issue(0, None, "invalid header size");
+            return;
+        }
+        self.header_size = header_size;
+
+        // Macros first, so calls can account for the loops and calls they make
+        self.macros = vec![MacroState::Pending; macro_count(header_size as u16)];
+        for index in 0..self.macros.len() {
+            self.walk_macro(index);
+        }
+        for channel in 0..TOTAL_VOICES {
+            if let Some(pointer) = self.header_pointer(channel_pointer_offset(channel), Some(channel)) {
+                self.walk(pointer, Some(channel));
+            }
+        }
+    }
+
+    /// Walk a macro if it wasn't already, returning its summary
+    fn walk_macro(&mut self, index: usize) -> MacroState {
+        if let MacroState::Pending = self.macros[index] {
+            self.macros[index] = MacroState::Walking;
+            let info = self.header_pointer(macro_pointer_offset(index as u8), None)
+                .map(|pointer| self.walk(pointer, None));
+            self.macros[index] = MacroState::Walked(info.map(|info| StreamInfo { max_calls: info.max_calls + 1, ..info }));
+        }
+        self.macros[index]
+    }
+
+    /// Walk a channel or macro stream until its `CHANNEL_END`
+    fn walk(&mut self, start: usize, channel: Option<usize>) -> StreamInfo {
+        let mut offset: usize = start;
+        let mut loops_active: usize = 0;
+        let mut max_loops: usize = 0;
+        let mut max_calls: usize = 0;
+        let mut max_note: u8 = 0;
+        let mut has_note: bool = false;
+        loop {
+            let instruction = match Instruction::decode(self.mmml_source, offset) {
+                Some(instruction) => instruction,
+                None => {
+                    self.issue(offset, channel, "missing channel end");
+                    break;
+                }
+            };
+            match instruction {
+                Instruction::LoopStart(count) => {
+                    loops_active += 1;
+                    max_loops = max_loops.max(loops_active);
+                    if loops_active > MAXLOOPS {
+                        self.issue(offset, channel, "too many nested loops");
+                    }
+                    if count == 0 {
+                        self.issue(offset, channel, "loop repeated zero times");
+                    }
+                },
+                Instruction::LoopEnd => {
+                    if loops_active == 0 {
+                        self.issue(offset, channel, "loop end without loop start");
+                    } else {
+                        loops_active -= 1;
+                    }
+                },
+                Instruction::Macro(index) if index as usize >= self.macros.len() => {
+                    self.issue(offset, channel, "macro index past the header table");
+                },
+                Instruction::Macro(index) => match self.walk_macro(index as usize) {
+                    MacroState::Walked(Some(info)) => {
+                        if loops_active + info.max_loops > MAXLOOPS {
+                            self.issue(offset, channel, "too many nested loops in macro");
+                        }
+                        if channel.is_some() && !self.is_single_macro_level && info.max_calls > MAX_MACRO_DEPTH {
+                            self.issue(offset, channel, "too many nested macro calls");
+                        }
+                        if channel == Some(TOTAL_VOICES - 1) && info.max_note as usize > self.sample_count {
+                            self.issue(offset, channel, "unknown percussion sample in macro");
+                        }
+                        max_loops = max_loops.max(loops_active + info.max_loops);
+                        max_calls = max_calls.max(info.max_calls);
+                        max_note = max_note.max(info.max_note);
+                        has_note = true;
+                    },
+                    // A single return address turns the call into a jump
+                    MacroState::Walking if self.is_single_macro_level => {},
+                    MacroState::Walking => self.issue(offset, channel, "recursive macro call"),
+                    _ => {}
+                },
+                Instruction::Octave(octave) if octave > 6 => {
+                    self.issue(offset, channel, "octave out of range");
+                },
+                Instruction::Note { note, .. } => {
+                    if channel == Some(TOTAL_VOICES - 1) && note as usize > self.sample_count {
+                        self.issue(offset, channel, "unknown percussion sample");
+                    }
+                    max_note = max_note.max(note);
+                    has_note = true;
+                },
+                // Skipped by the player
+                Instruction::Unknown(_) => self.warning(offset, channel, "unknown command"),
+                Instruction::ChannelEnd => {
+                    if loops_active > 0 {
+                        self.issue(offset, channel, "loop start without loop end");
+                    }
+                    if channel.is_some() && !has_note {
+                        self.issue(offset, channel, "channel without any note or rest");
+                    }
+                    break;
+                },
+                _ => {}
+            }
+            offset += instruction.size();
+        }
+        StreamInfo { max_loops, max_calls, max_note }
+    }
+}
@@ -0,0 +1,192 @@
+/// WAV file export
+///
+/// Synthesizes µMML bytecode into WAV files, either at the native PIM rate
+/// or resampled to a usual playback rate.
+use std::{fs::File, io::BufWriter, path::{Path, PathBuf}, str::FromStr};
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+
+use crate::{
+    error::MMMLError,
+    mmml_engine::{MixMode, MMMLSynthesizer, DC_OFFSET, TOTAL_VOICES},
+    sink::{render, AudioSink, EncodedSample, SampleEncoder, SinkWriter, StreamSpec}
+};
+
+/// Sample format of the WAV file (or raw samples)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WavFormat {
+    /// 8-bit with the raw 0 - 127 waveform levels, as the original player
+    #[default]
+    I8,
+    /// 16-bit, centered on zero
+    I16,
+    /// 24-bit, centered on zero
+    I24,
+    /// 32-bit float, centered on zero
+    F32
+}
+
+impl FromStr for WavFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "i8" => Ok(WavFormat::I8),
+            "i16" => Ok(WavFormat::I16),
+            "i24" => Ok(WavFormat::I24),
+            "f32" => Ok(WavFormat::F32),
+            _ => Err(format!("unknown sample format '{}', expected i8, i16, i24 or f32", name))
+        }
+    }
+}
+
+impl WavFormat {
+    fn spec(self, channels: u16, sample_rate: u32) -> WavSpec {
+        let (bits_per_sample, sample_format) = match self {
+            WavFormat::I8 => (8, SampleFormat::Int),
+            WavFormat::I16 => (16, SampleFormat::Int),
+            WavFormat::I24 => (24, SampleFormat::Int),
+            WavFormat::F32 => (32, SampleFormat::Float)
+        };
+        WavSpec { channels, sample_rate, bits_per_sample, sample_format }
+    }
+}
+
+/// Output settings of the WAV export
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WavOptions {
+    /// Sample rate of the WAV file, the synthesizer output is resampled when it differs
+    /// from `MMMLSynthesizer::sample_rate` (`None` keeps the native rate)
+    pub sample_rate: Option<u32>,
+    /// Sample format of the WAV file
+    pub format: WavFormat
+}
+
+/// Sink writing the samples into a WAV file
+pub struct WavSink {
+    path: PathBuf,
+    format: WavFormat,
+    writer: Option<WavWriter<BufWriter<File>>>,
+    encoder: Option<SampleEncoder>
+}
+
+impl WavSink {
+    pub fn new<P: AsRef<Path>>(path: P, format: WavFormat) -> Self {
+        WavSink { path: path.as_ref().to_path_buf(), format, writer: None, encoder: None }
+    }
+}
+
+impl AudioSink for WavSink {
+    fn open(&mut self, spec: StreamSpec) -> Result<(), MMMLError> {
+        self.writer = Some(WavWriter::create(&self.path, self.format.spec(spec.channels, spec.sample_rate))?);
+        self.encoder = Some(SampleEncoder::new(self.format, spec));
+        Ok(())
+    }
+
+    fn write(&mut self, levels: &[f32]) -> Result<(), MMMLError> {
+        let writer: &mut WavWriter<BufWriter<File>> = self.writer.as_mut().expect("WAV sink written before being opened");
+        let encoder: &mut SampleEncoder = self.encoder.as_mut().expect("WAV sink written before being opened");
+        for level in levels {
+            match encoder.encode(*level) {
+                EncodedSample::I8(sample) => writer.write_sample(sample)?,
+                EncodedSample::I16(sample) => writer.write_sample(sample)?,
+                EncodedSample::I24(sample) => writer.write_sample(sample)?,
+                EncodedSample::F32(sample) => writer.write_sample(sample)?
+            }
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), MMMLError> {
+        if let Some(writer) = self.writer.take() {
+            writer.finalize()?;
+        }
+        Ok(())
+    }
+}
+
+/// Synthesize µMML bytecode into a WAV file.
+///
+/// The synthesizer configuration (muted channels, stereo) is kept as is.
+pub fn export_wav<P: AsRef<Path>>(mmml: &mut MMMLSynthesizer, mmml_data: &[u8], path: P, options: &WavOptions) -> Result<(), MMMLError> {
+    render(mmml, mmml_data, &mut WavSink::new(path, options.format), options.sample_rate)
+}
+
+/// Path of the stem of a channel, `song.wav` gives `song_ch1.wav` for the first channel
+pub fn stem_path(path: &Path, channel: usize) -> PathBuf {
+    let stem: String = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+    path.with_file_name(format!("{}_ch{}", stem, channel + 1)).with_extension("wav")
+}
+
+/// Synthesize µMML bytecode into a mix WAV file plus one aligned WAV file per channel.
+///
+/// The stems are written next to the mix, see `stem_path`.
+pub fn export_stems<P: AsRef<Path>>(mmml: &mut MMMLSynthesizer, mmml_data: &[u8], path: P, options: &WavOptions) -> Result<(), MMMLError> {
+    if mmml.mix_mode == MixMode::Summed {
+        return export_summed_stems(mmml, mmml_data, path.as_ref(), options);
+    }
+    mmml.load(mmml_data)?;
+    let mut mix_sink: WavSink = WavSink::new(path.as_ref(), options.format);
+    let mut stem_sinks: Vec<WavSink> = (0..TOTAL_VOICES)
+        .map(|channel| WavSink::new(stem_path(path.as_ref(), channel), options.format))
+        .collect();
+    let mut mix: SinkWriter = SinkWriter::open(&mut mix_sink, mmml, options.sample_rate)?;
+    let mut stems: Vec<SinkWriter> = Vec::new();
+    for sink in stem_sinks.iter_mut() {
+        stems.push(SinkWriter::open(sink, mmml, options.sample_rate)?);
+    }
+
+    // Each frame holds one sample (or left / right pair) per voice, in order
+    let samples_per_voice: usize = if mmml.is_stereo { 2 } else { 1 };
+    let mut position: usize = 0;
+    let mut buffer: [u8; 4096] = [0; 4096];
+    loop {
+        let count: usize = mmml.fill(&mut buffer)?;
+        for sample in &buffer[..count] {
+            let voice: usize = position / samples_per_voice % TOTAL_VOICES;
+            mix.write(*sample)?;
+            for (channel, stem) in stems.iter_mut().enumerate() {
+                let stem_sample: u8 = if channel == voice { *sample } else { DC_OFFSET };
+                stem.write(stem_sample)?;
+            }
+            position += 1;
+        }
+        if count < buffer.len() {
+            break;
+        }
+    }
+    mix.close()?;
+    for stem in stems {
+        stem.close()?;
+    }
+    Ok(())
+}
+
+/// Summed samples can't be split by voice, the stems take the samples of each voice alone instead
+fn export_summed_stems(mmml: &mut MMMLSynthesizer, mmml_data: &[u8], path: &Path, options: &WavOptions) -> Result<(), MMMLError> {
+    mmml.load(mmml_data)?;
+    let mut mix_sink: WavSink = WavSink::new(path, options.format);
+    let mut stem_sinks: Vec<WavSink> = (0..TOTAL_VOICES)
+        .map(|channel| WavSink::new(stem_path(path, channel), options.format))
+        .collect();
+    let mut mix: SinkWriter = SinkWriter::open(&mut mix_sink, mmml, options.sample_rate)?;
+    let mut stems: Vec<SinkWriter> = Vec::new();
+    for sink in stem_sinks.iter_mut() {
+        stems.push(SinkWriter::open(sink, mmml, options.sample_rate)?);
+    }
+
+    while let Some(sample) = mmml.next() {
+        mix.write(sample)?;
+        for (stem, stem_sample) in stems.iter_mut().zip(mmml.voice_samples()) {
+            stem.write(stem_sample)?;
+        }
+    }
+    if let Some(err) = mmml.take_error() {
+        return Err(err);
+    }
+    mix.close()?;
+    for stem in stems {
+        stem.close()?;
+    }
+    Ok(())
+}
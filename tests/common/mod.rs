@@ -0,0 +1,13 @@
+/// Bytecode of the given channel and macro streams, header included
+pub fn bytecode(streams: &[&[u8]]) -> Vec<u8> {
+    let mut song: Vec<u8> = Vec::new();
+    let mut pointer: usize = streams.len() * 2;
+    for stream in streams {
+        song.extend_from_slice(&(pointer as u16).to_be_bytes());
+        pointer += stream.len();
+    }
+    for stream in streams {
+        song.extend_from_slice(stream);
+    }
+    song
+}
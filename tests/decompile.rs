@@ -0,0 +1,30 @@
+use mmml_player::{compile_mmml, decompile, get_mmml_data};
+
+/// Bytecode using loops, macros, dotted notes and every function command
+fn song() -> Vec<u8> {
+    vec![
+        // Header: channels A to D, then macro #0
+        0x00, 0x0A, 0x00, 0x24, 0x00, 0x2A, 0x00, 0x2F, 0x00, 0x32,
+        // A: t64 o3 v2 @1 k-2 p-64 [ c4 d4. [ e8 ]3 ]2 m0 & c16 r4 !
+        0xF3, 0x40, 0xD3, 0xE2, 0xF5, 0x01, 0xF4, 0xFE, 0xF7, 0xC0,
+        0xF0, 0x02, 0x12, 0x3A, 0xF0, 0x03, 0x53, 0xF1, 0xF1,
+        0xF2, 0x00, 0xF6, 0x14, 0x02, 0xFE, 0xFF,
+        // B: o4 m0 r2. a+32
+        0xD4, 0xF2, 0x00, 0x09, 0xB5, 0xFF,
+        // C: [ r1 ]4
+        0xF0, 0x04, 0x00, 0xF1, 0xFF,
+        // D: c4 e8.
+        0x12, 0x5B, 0xFF,
+        // #0: f8 g+16. b128
+        0x63, 0x9C, 0xC7, 0xFF,
+    ]
+}
+
+#[test]
+fn decompiled_source_recompiles_to_the_same_bytecode() {
+    let bytecode: Vec<u8> = song();
+    let source: String = decompile(&bytecode).expect("the bytecode decompiles");
+    let recompiled: Vec<u8> = get_mmml_data(compile_mmml(source.clone()).expect("the source compiles"))
+        .expect("the compiler output is bytecode");
+    assert_eq!(recompiled, bytecode, "source:\n{}", source);
+}
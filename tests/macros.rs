@@ -0,0 +1,89 @@
+mod common;
+
+use common::bytecode;
+use mmml_player::{
+    mmml_engine::{MAX_MACRO_DEPTH, NOTES},
+    validate, validate_for, MMMLError, MMMLSynthesizer, SequencerEvent, TOTAL_VOICES
+};
+
+const REST: &[u8] = &[0x00, 0xFF]; // r1
+
+/// Channel 1 calls macro #0, which calls macro #1, notes c, d and e telling where each returns
+fn nested_macros() -> Vec<u8> {
+    // 1: m0 c4 / #0: m1 d4 / #1: e4
+    bytecode(&[&[0xF2, 0x00, 0x12, 0xFF], REST, REST, REST, &[0xF2, 0x01, 0x32, 0xFF], &[0x52, 0xFF]])
+}
+
+/// Channel 1 calls a chain of `depth` nested macros, the last one playing c4
+fn macro_chain(depth: usize) -> Vec<u8> {
+    let calls: Vec<[u8; 3]> = (1..depth).map(|index| [0xF2, index as u8, 0xFF]).collect();
+    let mut streams: Vec<&[u8]> = vec![&[0xF2, 0x00, 0xFF], REST, REST, REST];
+    streams.extend(calls.iter().map(|call| &call[..]));
+    streams.push(&[0x12, 0xFF]);
+    bytecode(&streams)
+}
+
+/// Events of channel 1 until it restarts, the notes given by their index in `NOTES`
+fn channel_notes(mmml: &mut MMMLSynthesizer, song: &[u8]) -> Result<Vec<usize>, MMMLError> {
+    mmml.is_recording_events = true;
+    mmml.load(song)?;
+    let mut notes: Vec<usize> = Vec::new();
+    while mmml.next_tick()?.is_some() {
+        for event in mmml.take_events() {
+            match event {
+                SequencerEvent::Note { channel: 0, period, .. } => notes.push(NOTES.iter().position(|note| *note == period).unwrap()),
+                SequencerEvent::ChannelEnd { channel: 0 } => return Ok(notes),
+                _ => {}
+            }
+        }
+    }
+    Ok(notes)
+}
+
+#[test]
+fn nested_macros_return_to_their_caller() {
+    let notes: Vec<usize> = channel_notes(&mut MMMLSynthesizer::new(), &nested_macros()).unwrap();
+    // e (#1), then d (#0), then c (channel 1)
+    assert_eq!(notes, vec![5, 3, 1]);
+    assert_eq!(validate(&nested_macros()), Vec::new());
+}
+
+#[test]
+fn single_macro_level_does_not_return_to_the_outer_caller() {
+    let mut mmml: MMMLSynthesizer = MMMLSynthesizer::new();
+    mmml.is_single_macro_level = true;
+    let notes: Vec<usize> = channel_notes(&mut mmml, &nested_macros()).unwrap();
+    // #0 ends like the channel, c is never played
+    assert_eq!(notes, vec![5, 3]);
+}
+
+#[test]
+fn macro_depth_is_limited() {
+    let notes: Vec<usize> = channel_notes(&mut MMMLSynthesizer::new(), &macro_chain(MAX_MACRO_DEPTH)).unwrap();
+    assert_eq!(notes, vec![1]);
+    assert_eq!(validate(&macro_chain(MAX_MACRO_DEPTH)), Vec::new());
+
+    let too_deep: Vec<u8> = macro_chain(MAX_MACRO_DEPTH + 1);
+    let result = channel_notes(&mut MMMLSynthesizer::new(), &too_deep);
+    assert!(matches!(result, Err(MMMLError::MalformedBytecode { reason: "too many nested macro calls", .. })), "{:?}", result);
+    let issues = validate(&too_deep);
+    assert_eq!(issues.len(), 1);
+    // Reported at the call of channel 1, right after the header
+    let header_size: usize = (TOTAL_VOICES + MAX_MACRO_DEPTH + 1) * 2;
+    assert_eq!((issues[0].offset, issues[0].channel, issues[0].reason), (header_size, Some(0), "too many nested macro calls"));
+}
+
+#[test]
+fn single_macro_level_accepts_any_depth_and_recursion() {
+    let mut mmml: MMMLSynthesizer = MMMLSynthesizer::new();
+    mmml.is_single_macro_level = true;
+    let too_deep: Vec<u8> = macro_chain(MAX_MACRO_DEPTH + 1);
+    assert_eq!(channel_notes(&mut mmml, &too_deep).unwrap(), vec![1]);
+    assert_eq!(validate_for(&mmml, &too_deep), Vec::new());
+
+    // #0: c4 m0
+    let recursive: Vec<u8> = bytecode(&[&[0xF2, 0x00, 0xFF], REST, REST, REST, &[0x12, 0xF2, 0x00, 0xFF]]);
+    let issues = validate(&recursive);
+    assert_eq!(issues.iter().map(|issue| issue.reason).collect::<Vec<_>>(), vec!["recursive macro call"]);
+    assert_eq!(validate_for(&mmml, &recursive), Vec::new());
+}
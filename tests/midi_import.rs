@@ -0,0 +1,105 @@
+use mmml_player::{import_midi, MMMLError};
+
+const MIDI_DIVISION: u16 = 96; // MIDI ticks per quarter note, three times the µMML ticks
+
+fn write_vlq(data: &mut Vec<u8>, value: u32) {
+    let mut bytes: Vec<u8> = vec![(value & 0x7F) as u8];
+    let mut value: u32 = value >> 7;
+    while value > 0 {
+        bytes.push(((value & 0x7F) as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.reverse();
+    data.extend_from_slice(&bytes);
+}
+
+/// Track chunk of (delta time, event) pairs, closed by an end of track
+fn track(events: &[(u32, &[u8])]) -> Vec<u8> {
+    let mut data: Vec<u8> = Vec::new();
+    for (delta, event) in events {
+        write_vlq(&mut data, *delta);
+        data.extend_from_slice(event);
+    }
+    data.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+    let mut chunk: Vec<u8> = b"MTrk".to_vec();
+    chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    chunk.extend(data);
+    chunk
+}
+
+/// Format 1 MIDI file of the given tracks
+fn midi_file(tracks: &[Vec<u8>]) -> Vec<u8> {
+    let mut midi: Vec<u8> = b"MThd".to_vec();
+    midi.extend_from_slice(&6u32.to_be_bytes());
+    midi.extend_from_slice(&1u16.to_be_bytes());
+    midi.extend_from_slice(&(tracks.len() as u16).to_be_bytes());
+    midi.extend_from_slice(&MIDI_DIVISION.to_be_bytes());
+    for track in tracks {
+        midi.extend_from_slice(track);
+    }
+    midi
+}
+
+#[test]
+fn small_song_is_imported() {
+    let quarter: u32 = MIDI_DIVISION as u32;
+    let midi: Vec<u8> = midi_file(&[
+        // 100 BPM
+        track(&[(0, &[0xFF, 0x51, 0x03, 0x09, 0x27, 0xC0])]),
+        // a4 for a quarter, a quarter rest, then a4 held for five quarters (a whole note tied to a quarter)
+        track(&[
+            (0, &[0x90, 69, 100]), (quarter, &[0x80, 69, 0]),
+            (quarter, &[0x90, 69, 50]), (quarter * 5 + 1, &[0x80, 69, 0])
+        ]),
+        // Kick drum on the first beat, slightly early
+        track(&[(0, &[0x99, 36, 100]), (quarter / 2 - 1, &[0x89, 36, 0])])
+    ]);
+    assert_eq!(import_midi(&midi).unwrap(), vec![
+        // Header
+        0x00, 0x08, 0x00, 0x13, 0x00, 0x16, 0x00, 0x19,
+        // 1: t83 o3 v1 e4 r4 v3 e1 & e4 (a4 being closest to e of octave 3)
+        0xF3, 0x53, 0xD3, 0xE1, 0x52, 0x02, 0xE3, 0x50, 0xF6, 0x52, 0xFF,
+        // 2 and 3: rests lasting the whole song
+        0x00, 0x08, 0xFF,
+        0x00, 0x08, 0xFF,
+        // 4: kick8, then rests
+        0x33, 0x00, 0x01, 0x03, 0xFF
+    ]);
+}
+
+/// Track of `count` eighth notes on a key
+fn eighth_notes(key: u8, count: usize) -> Vec<u8> {
+    let eighth: u32 = MIDI_DIVISION as u32 / 2;
+    let note_on: [u8; 3] = [0x90, key, 100];
+    let note_off: [u8; 3] = [0x80, key, 0];
+    let events: Vec<(u32, &[u8])> = (0..count).flat_map(|_| [(0, &note_on[..]), (eighth, &note_off[..])]).collect();
+    track(&events)
+}
+
+#[test]
+fn only_the_three_busiest_tracks_are_kept() {
+    // The track of a single note is left out, the busiest one going to channel 1
+    let midi: Vec<u8> = midi_file(&[eighth_notes(62, 1), eighth_notes(61, 2), eighth_notes(64, 4), eighth_notes(65, 3)]);
+    assert_eq!(import_midi(&midi).unwrap(), vec![
+        0x00, 0x08, 0x00, 0x11, 0x00, 0x18, 0x00, 0x1E,
+        // 1: t69 o2 v1 b8 b8 b8 b8
+        0xF3, 0x45, 0xD2, 0xE1, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF,
+        // 2: o3 v1 c8 c8 c8 r8
+        0xD3, 0xE1, 0x13, 0x13, 0x13, 0x03, 0xFF,
+        // 3: o2 v1 g+8 g+8 r4
+        0xD2, 0xE1, 0x93, 0x93, 0x02, 0xFF,
+        // 4: r2
+        0x01, 0xFF
+    ]);
+}
+
+#[test]
+fn songs_too_long_for_16_bit_pointers_are_rejected() {
+    // Every note takes a byte, alternating keys so none can be merged
+    let sixteenth: u32 = MIDI_DIVISION as u32 / 4;
+    let notes: Vec<[u8; 3]> = (0..70000).map(|index| [0x90, 60 + (index % 2) as u8, 100]).collect();
+    let offs: Vec<[u8; 3]> = notes.iter().map(|note| [0x80, note[1], 0]).collect();
+    let events: Vec<(u32, &[u8])> = notes.iter().zip(&offs).flat_map(|(on, off)| [(0, &on[..]), (sixteenth, &off[..])]).collect();
+    let result = import_midi(&midi_file(&[track(&events)]));
+    assert!(matches!(result, Err(MMMLError::InvalidMidi("song too long for µMML data"))), "{:?}", result.map(|data| data.len()));
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use common::bytecode;
+use mmml_player::{render, EndCondition, MMMLError, MMMLSynthesizer, NullSink, SongPosition, TOTAL_VOICES};
+
+/// Four identical channels, ending together: t64 [ c4 ]100
+fn song() -> Vec<u8> {
+    let channel: &[u8] = &[0xF3, 0x40, 0xF0, 0x64, 0x12, 0xF1, 0xFF];
+    bytecode(&[channel; TOTAL_VOICES])
+}
+
+/// Number of frames rendered between a start position and an end condition
+fn rendered_frames(start: Option<SongPosition>, end: EndCondition) -> Result<u64, MMMLError> {
+    let mut mmml: MMMLSynthesizer = MMMLSynthesizer::new();
+    mmml.start_position = start;
+    mmml.end_condition = end;
+    let mut sink: NullSink = NullSink::default();
+    render(&mut mmml, &song(), &mut sink, None)?;
+    Ok(sink.samples / TOTAL_VOICES as u64)
+}
+
+#[test]
+fn duration_is_counted_from_the_start_position() {
+    let frames: u64 = rendered_frames(None, EndCondition::Duration(5000)).unwrap();
+    assert_eq!(frames, 5000);
+    let frames: u64 = rendered_frames(Some(SongPosition::Frames(10000)), EndCondition::Duration(5000)).unwrap();
+    assert_eq!(frames, 5000);
+    let frames: u64 = rendered_frames(Some(SongPosition::Ticks(3)), EndCondition::Duration(5000)).unwrap();
+    assert_eq!(frames, 5000);
+}
+
+#[test]
+fn end_position_stops_after_the_start_position() {
+    let frames: u64 = rendered_frames(Some(SongPosition::Frames(10000)), EndCondition::Position(SongPosition::Frames(12000))).unwrap();
+    assert_eq!(frames, 2000);
+}
+
+#[test]
+fn end_position_before_the_start_position_is_rejected() {
+    for end in [SongPosition::Frames(10000), SongPosition::Frames(5000)] {
+        let result = rendered_frames(Some(SongPosition::Frames(10000)), EndCondition::Position(end));
+        assert!(matches!(result, Err(MMMLError::InvalidPosition(_))), "end {:?}: {:?}", end, result);
+    }
+}
+
+#[test]
+fn start_position_past_the_end_of_the_song_is_rejected() {
+    let length: u64 = rendered_frames(None, EndCondition::AllChannels).unwrap();
+    assert!(length > 0);
+    let result = rendered_frames(Some(SongPosition::Frames(length + 1)), EndCondition::AllChannels);
+    assert!(matches!(result, Err(MMMLError::InvalidPosition(_))), "{:?}", result);
+    let frames: u64 = rendered_frames(Some(SongPosition::Frames(length / 2)), EndCondition::AllChannels).unwrap();
+    assert_eq!(frames, length - length / 2);
+}
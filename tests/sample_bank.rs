@@ -0,0 +1,49 @@
+use mmml_player::{MMMLError, SampleBank};
+
+#[test]
+fn bank_files_round_trip() {
+    let builtin: SampleBank = SampleBank::builtin();
+    assert_eq!(SampleBank::from_bytes(&builtin.to_bytes()).unwrap(), builtin);
+
+    let mut bank: SampleBank = SampleBank::builtin();
+    assert_eq!(bank.push(&[0b10101010, 0b11110000, 0]).unwrap(), 6);
+    let bytes: Vec<u8> = bank.to_bytes();
+    assert_eq!(bytes[0], 6);
+    // Offset table: the 5 built-in samples, then the new one ending 3 bytes after them
+    assert_eq!(&bytes[1..15], &[0, 0, 0, 19, 0, 34, 0, 74, 0, 118, 0, 126, 0, 129]);
+    assert_eq!(&bytes[bytes.len() - 3..], &[0b10101010, 0b11110000, 0]);
+    let loaded: SampleBank = SampleBank::from_bytes(&bytes).unwrap();
+    assert_eq!(loaded, bank);
+    assert_eq!(loaded.sample_count(), 6);
+}
+
+#[test]
+fn invalid_bank_files_are_rejected() {
+    let banks: [&[u8]; 7] = [
+        // Empty file
+        &[],
+        // No sample
+        &[0, 0, 0],
+        // Too many samples
+        &[13],
+        // Truncated offset table
+        &[2, 0, 0, 0, 1],
+        // Offsets out of order
+        &[2, 0, 0, 0, 2, 0, 1, 0xAA, 0xAA],
+        // Empty sample
+        &[2, 0, 0, 0, 0, 0, 2, 0xAA, 0xAA],
+        // Offset past the bitstream
+        &[1, 0, 0, 0, 3, 0xAA, 0xAA]
+    ];
+    for bytes in banks {
+        let result = SampleBank::from_bytes(bytes);
+        assert!(matches!(result, Err(MMMLError::InvalidSampleBank(_))), "{:?}: {:?}", bytes, result);
+    }
+}
+
+#[test]
+fn empty_samples_are_not_added() {
+    let mut bank: SampleBank = SampleBank::builtin();
+    assert!(matches!(bank.push(&[]), Err(MMMLError::InvalidSampleBank(_))));
+    assert_eq!(bank, SampleBank::builtin());
+}
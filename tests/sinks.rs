@@ -0,0 +1,84 @@
+mod common;
+
+use common::bytecode;
+use mmml_player::{
+    render, AudioSink, EndCondition, MixMode, MMMLSynthesizer, NullSink, RawSink, StreamSpec, WavFormat, WavSink, TOTAL_VOICES
+};
+
+const FRAMES: u64 = 1000;
+
+/// Four channels playing c4 over and over, channel 1 panned hard left (p-127)
+fn song() -> Vec<u8> {
+    bytecode(&[&[0xF7, 0x81, 0x12, 0xFF], &[0x12, 0xFF], &[0x12, 0xFF], &[0x12, 0xFF]])
+}
+
+fn synthesizer(mix_mode: MixMode, is_stereo: bool) -> MMMLSynthesizer {
+    let mut mmml: MMMLSynthesizer = MMMLSynthesizer::new();
+    mmml.mix_mode = mix_mode;
+    mmml.is_stereo = is_stereo;
+    mmml.end_condition = EndCondition::Duration(FRAMES);
+    mmml
+}
+
+#[test]
+fn null_sink_counts_the_samples_of_every_mode() {
+    let modes: [(MixMode, bool, u64, u16); 4] = [
+        (MixMode::Pim, false, FRAMES * TOTAL_VOICES as u64, 1),
+        (MixMode::Pim, true, FRAMES * TOTAL_VOICES as u64 * 2, 2),
+        (MixMode::Summed, false, FRAMES, 1),
+        (MixMode::Summed, true, FRAMES * 2, 2)
+    ];
+    for (mix_mode, is_stereo, samples, channels) in modes {
+        let mut mmml: MMMLSynthesizer = synthesizer(mix_mode, is_stereo);
+        let mut sink: NullSink = NullSink::default();
+        render(&mut mmml, &song(), &mut sink, None).unwrap();
+        assert_eq!(sink.samples, samples, "{:?}, stereo {}", mix_mode, is_stereo);
+        assert_eq!(sink.spec, Some(StreamSpec { channels, sample_rate: mmml.sample_rate() }));
+    }
+}
+
+#[test]
+fn raw_sink_writes_little_endian_samples() {
+    let formats: [(WavFormat, Vec<u8>); 4] = [
+        (WavFormat::I8, vec![0, 127]),
+        (WavFormat::I16, vec![0, 0, 0xFF, 0x7F]),
+        (WavFormat::I24, vec![0, 0, 0, 0xFF, 0xFF, 0x7F]),
+        (WavFormat::F32, [0.0f32.to_le_bytes(), 1.0f32.to_le_bytes()].concat())
+    ];
+    for (format, expected) in formats {
+        let mut output: Vec<u8> = Vec::new();
+        let mut sink: RawSink<&mut Vec<u8>> = RawSink::new(&mut output, format);
+        sink.open(StreamSpec { channels: 1, sample_rate: 44100 }).unwrap();
+        // A step from the low to the high waveform position
+        sink.write(&[0.0, 127.0]).unwrap();
+        sink.close().unwrap();
+        assert_eq!(output, expected, "{:?}", format);
+    }
+}
+
+#[test]
+fn stereo_samples_are_interleaved() {
+    let mut mmml: MMMLSynthesizer = synthesizer(MixMode::Pim, true);
+    // Channel 1 alone, hard left
+    for channel in mmml.channels.iter_mut().skip(1) {
+        channel.is_muted = true;
+    }
+    let mut output: Vec<u8> = Vec::new();
+    render(&mut mmml, &song(), &mut RawSink::new(&mut output, WavFormat::I8), None).unwrap();
+    assert_eq!(output.len() as u64, FRAMES * TOTAL_VOICES as u64 * 2);
+    assert!(output.chunks(2).any(|pair| pair[0] > 0), "the left channel is silent");
+    assert!(output.chunks(2).all(|pair| pair[1] == 0), "the right channel plays");
+}
+
+#[test]
+fn wav_sink_writes_every_sample() {
+    let path = std::env::temp_dir().join(format!("mmml-player-sink-{}.wav", std::process::id()));
+    let mut mmml: MMMLSynthesizer = synthesizer(MixMode::Summed, true);
+    render(&mut mmml, &song(), &mut WavSink::new(&path, WavFormat::I16), None).unwrap();
+    let reader = hound::WavReader::open(&path).unwrap();
+    let spec = reader.spec();
+    let samples: u32 = reader.len();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!((spec.channels, spec.sample_rate, spec.bits_per_sample), (2, mmml.sample_rate(), 16));
+    assert_eq!(samples as u64, FRAMES * 2);
+}
@@ -0,0 +1,29 @@
+mod common;
+
+use common::bytecode;
+use mmml_player::{validate, Severity, ValidationIssue};
+
+/// A channel calls macro #0, which calls macro #1 playing note 9 (no such built-in sample)
+fn nested_percussion_macro(caller: usize) -> Vec<u8> {
+    let mut streams: Vec<&[u8]> = vec![&[0x00, 0xFF]; 4];
+    // m0 instead of r1
+    streams[caller] = &[0xF2, 0x00, 0xFF];
+    // #0: m1
+    streams.push(&[0xF2, 0x01, 0xFF]);
+    // #1: a1
+    streams.push(&[0x90, 0xFF]);
+    bytecode(&streams)
+}
+
+#[test]
+fn unknown_percussion_sample_in_macro_is_reported_at_the_call() {
+    let issues: Vec<ValidationIssue> = validate(&nested_percussion_macro(3));
+    assert_eq!(issues, vec![ValidationIssue {
+        offset: 0x12, // header and 3 rests
+        channel: Some(3),
+        reason: "unknown percussion sample in macro",
+        severity: Severity::Error
+    }]);
+    // Pulse voices play any note
+    assert_eq!(validate(&nested_percussion_macro(0)), Vec::new());
+}